@@ -0,0 +1,197 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+pub const PROJECT_CONFIG_FILE_NAME: &str = ".ruplacer.toml";
+
+/// Default values for some of the command line options, read from
+/// `.ruplacer.toml` files in the project, or from the user configuration file.
+///
+/// ```toml
+/// hidden = true
+/// color = "always"
+/// type-not = ["lock", "*.min.js"]
+/// exclude = ["vendor/**"]
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub hidden: Option<bool>,
+    pub ignored: Option<bool>,
+    pub ignore_case: Option<bool>,
+    pub smart_case: Option<bool>,
+    pub color: Option<String>,
+    pub scope: Option<String>,
+    #[serde(rename = "type")]
+    pub selected_file_types: Option<Vec<String>>,
+    #[serde(rename = "type-not")]
+    pub ignored_file_types: Option<Vec<String>>,
+    #[serde(rename = "include")]
+    pub included_globs: Option<Vec<String>>,
+    #[serde(rename = "exclude")]
+    pub excluded_globs: Option<Vec<String>>,
+}
+
+impl Config {
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Could not parse {}", path.display()))
+    }
+
+    /// Return a new config, where values from `other` take precedence.
+    /// Note that enabling `ignore-case` or `smart-case` in `other` disables
+    /// the other case option from `self`
+    pub fn merge(self, other: Config) -> Config {
+        let (ignore_case, smart_case) = match (other.ignore_case, other.smart_case) {
+            (Some(true), smart_case) => (Some(true), smart_case.or(Some(false))),
+            (ignore_case, Some(true)) => (ignore_case.or(Some(false)), Some(true)),
+            (ignore_case, smart_case) => (
+                ignore_case.or(self.ignore_case),
+                smart_case.or(self.smart_case),
+            ),
+        };
+        Config {
+            hidden: other.hidden.or(self.hidden),
+            ignored: other.ignored.or(self.ignored),
+            ignore_case,
+            smart_case,
+            color: other.color.or(self.color),
+            scope: other.scope.or(self.scope),
+            selected_file_types: other.selected_file_types.or(self.selected_file_types),
+            ignored_file_types: other.ignored_file_types.or(self.ignored_file_types),
+            included_globs: other.included_globs.or(self.included_globs),
+            excluded_globs: other.excluded_globs.or(self.excluded_globs),
+        }
+    }
+
+    /// Load the user configuration file, then the closest `.ruplacer.toml` file
+    /// found when walking up from `path`. Values from the project take precedence
+    /// over values from the user configuration file.
+    pub fn load(path: &Path) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(user_config_path) = user_config_path() {
+            if user_config_path.is_file() {
+                config = config.merge(Config::from_file(&user_config_path)?);
+            }
+        }
+        if let Some(project_config_path) = find_project_config(path) {
+            config = config.merge(Config::from_file(&project_config_path)?);
+        }
+        Ok(config)
+    }
+}
+
+/// Look for a `.ruplacer.toml` file in `path` and its parents
+fn find_project_config(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    path.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Return the path of the user configuration file:
+///  * `%APPDATA%\ruplacer\config.toml` on Windows
+///  * `$XDG_CONFIG_HOME/ruplacer/config.toml`, or `~/.config/ruplacer/config.toml` otherwise
+fn user_config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(config_dir.join("ruplacer").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_merge_configs() {
+        let user_config = Config {
+            hidden: Some(true),
+            color: Some("never".to_string()),
+            ..Default::default()
+        };
+        let project_config = Config {
+            color: Some("always".to_string()),
+            selected_file_types: Some(vec!["rust".to_string()]),
+            ..Default::default()
+        };
+        let actual = user_config.merge(project_config);
+        let expected = Config {
+            hidden: Some(true),
+            color: Some("always".to_string()),
+            selected_file_types: Some(vec!["rust".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_merge_case_options() {
+        let user_config = Config {
+            ignore_case: Some(true),
+            hidden: Some(true),
+            ..Default::default()
+        };
+        let project_config = Config {
+            smart_case: Some(true),
+            hidden: Some(false),
+            ..Default::default()
+        };
+        let actual = user_config.merge(project_config);
+        let expected = Config {
+            ignore_case: Some(false),
+            smart_case: Some(true),
+            hidden: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(actual, expected);
+
+        // Disabling a case option leaves the other one alone
+        let user_config = Config {
+            ignore_case: Some(true),
+            ..Default::default()
+        };
+        let project_config = Config {
+            smart_case: Some(false),
+            ..Default::default()
+        };
+        let actual = user_config.merge(project_config);
+        assert_eq!(actual.ignore_case, Some(true));
+        assert_eq!(actual.smart_case, Some(false));
+    }
+
+    #[test]
+    fn test_find_project_config() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let top = temp_dir.path();
+        let sub_dir = top.join("src/sub");
+        fs::create_dir_all(&sub_dir).unwrap();
+        fs::write(
+            top.join(PROJECT_CONFIG_FILE_NAME),
+            "hidden = true\ntype-not = ['lock']\n",
+        )
+        .unwrap();
+
+        let config_path = find_project_config(&sub_dir).unwrap();
+        let config = Config::from_file(&config_path).unwrap();
+        assert_eq!(config.hidden, Some(true));
+        assert_eq!(config.ignored_file_types, Some(vec!["lock".to_string()]));
+    }
+
+    #[test]
+    fn test_reject_unknown_keys() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let config_path = temp_dir.path().join(PROJECT_CONFIG_FILE_NAME);
+        fs::write(&config_path, "hiden = true\n").unwrap();
+
+        let err = Config::from_file(&config_path).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field"));
+    }
+}
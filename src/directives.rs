@@ -0,0 +1,178 @@
+/// A `ruplacer: <directive>` comment found in a source file.
+/// Directives must directly follow a comment marker, but are recognized
+/// regardless of the comment syntax, so `// ruplacer: disable` and
+/// `# ruplacer: disable` both work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Directive {
+    /// Leave the next line untouched
+    DisableNextLine,
+    /// Leave every line untouched until the next `enable` directive
+    Disable,
+    Enable,
+    /// Leave the whole file untouched
+    DisableFile,
+}
+
+const PREFIX: &str = "ruplacer:";
+
+/// Line and block comment markers of the most common languages
+const COMMENT_MARKERS: &[&str] = &[
+    "//", "///", "//!", "/*", "/**", "*", "#", "##", "<!--", "--", ";", ";;", "%",
+];
+
+impl Directive {
+    fn parse(line: &str) -> Option<Self> {
+        line.match_indices(PREFIX)
+            .filter(|(index, _)| is_after_comment_marker(&line[..*index]))
+            .find_map(|(index, _)| Self::parse_name(&line[index + PREFIX.len()..]))
+    }
+
+    fn parse_name(rest: &str) -> Option<Self> {
+        let rest = rest.trim_start();
+        // Note: order matters, as "disable" is a prefix of the other
+        // disable directives
+        for (name, directive) in &[
+            ("disable-next-line", Directive::DisableNextLine),
+            ("disable-file", Directive::DisableFile),
+            ("disable", Directive::Disable),
+            ("enable", Directive::Enable),
+        ] {
+            if let Some(after) = rest.strip_prefix(name) {
+                let is_word_end =
+                    !after.starts_with(|c: char| c.is_alphanumeric() || c == '-' || c == '_');
+                if is_word_end {
+                    return Some(*directive);
+                }
+            }
+        }
+        None
+    }
+}
+
+// Return true if `before` ends with a comment marker, followed by optional
+// whitespace. The marker must start a word, so that `"// ruplacer: disable"`
+// in a string literal is not a directive
+fn is_after_comment_marker(before: &str) -> bool {
+    let before = before.trim_end();
+    COMMENT_MARKERS
+        .iter()
+        .filter_map(|marker| before.strip_suffix(marker))
+        .any(|start| start.is_empty() || start.ends_with(char::is_whitespace))
+}
+
+/// Return true if one of the lines contains a `ruplacer: disable-file` directive
+pub(crate) fn is_file_disabled<'a>(mut lines: impl Iterator<Item = &'a str>) -> bool {
+    lines.any(|line| Directive::parse(line) == Some(Directive::DisableFile))
+}
+
+/// Keep track of the directives found while going through the lines of a file
+#[derive(Default)]
+pub(crate) struct Directives {
+    in_disabled_block: bool,
+    disable_next_line: bool,
+}
+
+impl Directives {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Must be called for every line, in order.
+    /// Return true if the line must be left untouched. Note that lines containing
+    /// directives are always left untouched.
+    pub(crate) fn is_disabled(&mut self, line: &str) -> bool {
+        let disabled_by_previous_line = self.disable_next_line;
+        self.disable_next_line = false;
+        let directive = match Directive::parse(line) {
+            None => return disabled_by_previous_line || self.in_disabled_block,
+            Some(directive) => directive,
+        };
+        match directive {
+            Directive::DisableNextLine => self.disable_next_line = true,
+            Directive::Disable => self.in_disabled_block = true,
+            Directive::Enable => self.in_disabled_block = false,
+            Directive::DisableFile => (),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive() {
+        assert_eq!(
+            Directive::parse("// ruplacer: disable-next-line"),
+            Some(Directive::DisableNextLine)
+        );
+        assert_eq!(
+            Directive::parse("# ruplacer:disable"),
+            Some(Directive::Disable)
+        );
+        assert_eq!(
+            Directive::parse("<!-- ruplacer: enable -->"),
+            Some(Directive::Enable)
+        );
+        assert_eq!(
+            Directive::parse("/* ruplacer: disable-file */"),
+            Some(Directive::DisableFile)
+        );
+        assert_eq!(Directive::parse("// ruplacer: disabled"), None);
+        assert_eq!(
+            Directive::parse("let x = 1; # ruplacer: disable-next-line"),
+            Some(Directive::DisableNextLine)
+        );
+        assert_eq!(
+            Directive::parse(" * ruplacer: disable"),
+            Some(Directive::Disable)
+        );
+        assert_eq!(
+            Directive::parse("/// ruplacer: enable"),
+            Some(Directive::Enable)
+        );
+        assert_eq!(Directive::parse("// ruplacer: disabled"), None);
+        assert_eq!(Directive::parse("let ruplacer = 42;"), None);
+    }
+
+    #[test]
+    fn test_directives_must_be_in_comments() {
+        assert_eq!(Directive::parse("ruplacer: disable"), None);
+        assert_eq!(Directive::parse(r#"let s = "ruplacer: disable";"#), None);
+        assert_eq!(Directive::parse(r#"let s = "// ruplacer: disable";"#), None);
+        assert_eq!(Directive::parse("foo(); ruplacer: disable"), None);
+        assert_eq!(
+            Directive::parse(r#"let s = "ruplacer: disable"; // ruplacer: enable"#),
+            Some(Directive::Enable)
+        );
+    }
+
+    #[test]
+    fn test_disabled_lines() {
+        let lines = [
+            "old",
+            "// ruplacer: disable-next-line",
+            "old",
+            "old",
+            "# ruplacer: disable",
+            "old",
+            "old",
+            "# ruplacer: enable",
+            "old",
+        ];
+        let mut directives = Directives::new();
+        let actual: Vec<_> = lines
+            .iter()
+            .map(|line| directives.is_disabled(line))
+            .collect();
+        assert_eq!(
+            actual,
+            vec![false, true, true, false, true, true, true, true, false]
+        );
+        assert!(!is_file_disabled(lines.iter().copied()));
+        assert!(is_file_disabled(
+            ["old", "# ruplacer: disable-file"].iter().copied()
+        ));
+    }
+}
@@ -1,85 +1,375 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
+use crate::error::{Error, Result};
 use crate::file_patcher::FilePatcher;
+use crate::git;
+use crate::identifiers::missing_feature_error;
+use crate::lexer::LanguageDetector;
 use crate::query::Query;
-use crate::settings::Settings;
-use crate::stats::Stats;
+use crate::settings::{OutputFormat, Scope, Settings};
+use crate::stats::{Outcome, Stats};
+
+/// Name of the files listing paths ruplacer should never touch, using
+/// the same syntax as `.gitignore` files
+const RUPLACERIGNORE_FILE_NAME: &str = ".ruplacerignore";
 
 #[derive(Debug)]
-/// Used to run replacement query on every text file present in a given path
+/// Used to run replacement query on every text file present in one or several paths
 /// ```rust
 /// use ruplacer::{DirectoryPatcher, Query, Settings, Stats};
 /// use std::path::PathBuf;
 ///
-/// let settings = Settings{
-///     dry_run: true,
-///     .. Default::default()
-/// };
+/// let settings = Settings::builder().dry_run(true).build();
 /// let path = PathBuf::from("tests/data");
 /// let mut directory_patcher = DirectoryPatcher::new(&path, &settings);
 ///
 /// let query = Query::substring("old", "new");
-/// directory_patcher.run(&query).unwrap();
+/// let outcome = directory_patcher.run(&query).unwrap();
+/// println!("{:?}", outcome);
 /// let stats = directory_patcher.stats();
 /// println!("Found {} matching lines", stats.matching_lines());
+///
+/// // Several paths can be given, files are only patched once
+/// let paths = vec![PathBuf::from("tests/data"), PathBuf::from("tests/data/top.txt")];
+/// let mut directory_patcher = DirectoryPatcher::with_paths(&paths, &settings);
+/// directory_patcher.run(&query).unwrap();
 /// ```
 // Note: keep the dry_run: true in the doc test above or the integration test
 // will fail ...
 pub struct DirectoryPatcher<'a> {
-    path: &'a Path,
+    paths: Vec<&'a Path>,
+    // Whether self.paths is a list of files to patch, rather than paths to walk
+    listed_files: bool,
     settings: &'a Settings,
     stats: Stats,
+    language_detector: Option<LanguageDetector>,
+    // Canonical paths of the files selected by git, if any
+    git_files: Option<HashSet<PathBuf>>,
+    // Canonical paths of the files ruplacer must not write to
+    dirty_files: HashSet<PathBuf>,
+    // Files to write once every file has been checked, when some files
+    // are dirty
+    pending: Vec<FilePatcher>,
 }
 
 impl<'a> DirectoryPatcher<'a> {
     pub fn new(path: &'a Path, settings: &'a Settings) -> DirectoryPatcher<'a> {
+        Self::from_paths(vec![path], settings)
+    }
+
+    /// Create a DirectoryPatcher running on several paths. Each path can be
+    /// a directory or a file
+    pub fn with_paths(paths: &'a [PathBuf], settings: &'a Settings) -> DirectoryPatcher<'a> {
+        Self::from_paths(paths.iter().map(|p| p.as_path()).collect(), settings)
+    }
+
+    /// Create a DirectoryPatcher running on the given list of files, without
+    /// walking any directory. The file types, globs, hidden files and file size
+    /// filters only apply if `settings.filter_listed_files` is true. Globs are
+    /// then relative to the working directory.
+    pub fn with_files(files: &'a [PathBuf], settings: &'a Settings) -> DirectoryPatcher<'a> {
+        let mut patcher = Self::with_paths(files, settings);
+        patcher.listed_files = true;
+        patcher
+    }
+
+    /// Return a builder for a DirectoryPatcher using the given settings
+    ///
+    /// ```rust
+    /// use ruplacer::{DirectoryPatcher, Query, Settings};
+    /// use std::path::Path;
+    ///
+    /// let settings = Settings::builder().dry_run(true).build();
+    /// let mut directory_patcher = DirectoryPatcher::builder(&settings)
+    ///     .path(Path::new("tests/data/a_dir"))
+    ///     .path(Path::new("tests/data/top.txt"))
+    ///     .build();
+    /// directory_patcher.run(&Query::substring("old", "new")).unwrap();
+    /// ```
+    pub fn builder(settings: &'a Settings) -> DirectoryPatcherBuilder<'a> {
+        DirectoryPatcherBuilder {
+            paths: vec![],
+            listed_files: false,
+            settings,
+        }
+    }
+
+    fn from_paths(paths: Vec<&'a Path>, settings: &'a Settings) -> DirectoryPatcher<'a> {
         let stats = Stats::default();
         DirectoryPatcher {
-            path,
+            paths,
+            listed_files: false,
             settings,
             stats,
+            language_detector: None,
+            git_files: None,
+            dirty_files: HashSet::new(),
+            pending: vec![],
+        }
+    }
+
+    /// Run the given query on the selected files in self.paths.
+    /// Files that cannot be read or written are skipped, and their errors collected
+    /// in `Stats::errors()`, see `Outcome::PartialErrors`. Nothing is printed on stderr
+    pub fn run(&mut self, query: &Query) -> Result<Outcome> {
+        if self.listed_files {
+            self.run_on_listed_files(query)?;
+        } else {
+            self.run_on_paths(query)?;
+        }
+        self.write_pending()?;
+        Ok(self.stats.outcome())
+    }
+
+    // Note: there is no undo, so refuse to write anything if one of the
+    // files has changes git cannot restore
+    fn write_pending(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        let dirty: Vec<_> = pending
+            .iter()
+            .map(|file_patcher| file_patcher.path())
+            .filter(|path| {
+                let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                self.dirty_files.contains(&canonical_path)
+            })
+            .map(|path| path.to_path_buf())
+            .collect();
+        if !dirty.is_empty() {
+            return Err(Error::DirtyFiles { paths: dirty });
+        }
+        for file_patcher in pending {
+            self.write(file_patcher);
+        }
+        Ok(())
+    }
+
+    // Note: keep writing the other files if one of them cannot be written
+    fn write(&mut self, file_patcher: FilePatcher) {
+        if let Err(error) = file_patcher.run() {
+            self.report_error(error);
         }
     }
 
-    /// Run the given query on the selected files in self.path
-    pub fn run(&mut self, query: &Query) -> Result<()> {
-        let walker = self.build_walker()?;
-        for entry in walker {
-            let entry = entry.with_context(|| "Could not read directory entry")?;
+    fn run_on_paths(&mut self, query: &Query) -> Result<()> {
+        // Note: build all the walkers first, so that errors in the
+        // settings are reported before any file is patched
+        let walkers = self
+            .paths
+            .iter()
+            .map(|path| self.build_walker(path))
+            .collect::<Result<Vec<_>>>()?;
+        self.prepare()?;
+        // Paths may overlap, so make sure each file is only patched once
+        let mut seen = HashSet::new();
+        for entry in walkers.into_iter().flatten() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) if is_loop(&err) => {
+                    self.stats.add_warning(Error::Walk(err));
+                    continue;
+                }
+                Err(err) => {
+                    self.report_error(Error::Walk(err));
+                    continue;
+                }
+            };
             if let Some(file_type) = entry.file_type() {
                 if file_type.is_file() {
-                    self.patch_file(&entry.path(), &query)?;
+                    self.patch_file_once(&mut seen, entry.path(), query)?;
                 }
             }
         }
         Ok(())
     }
 
+    fn run_on_listed_files(&mut self, query: &Query) -> Result<()> {
+        // Note: listed files may be absolute, or contain '..', so match
+        // their canonical path against a canonical root
+        let root = Path::new(".")
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from("."));
+        let filters = if self.settings.filter_listed_files {
+            Some((self.build_types()?, self.build_overrides(&root)?))
+        } else {
+            None
+        };
+        self.prepare()?;
+        let mut seen = HashSet::new();
+        for path in self.paths.clone() {
+            let metadata = match std::fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(source) => {
+                    self.report_error(Error::Read {
+                        path: path.to_path_buf(),
+                        source,
+                    });
+                    continue;
+                }
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            if let Some((types, overrides)) = &filters {
+                let too_large =
+                    matches!(self.settings.max_filesize, Some(max) if metadata.len() > max);
+                if too_large || !self.is_listed_file_selected(&root, path, types, overrides) {
+                    continue;
+                }
+            }
+            self.patch_file_once(&mut seen, path, query)?;
+        }
+        Ok(())
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        if self.settings.scope == Scope::Identifiers && !cfg!(feature = "tree-sitter") {
+            return Err(missing_feature_error());
+        }
+        if self.settings.scope != Scope::All {
+            self.language_detector = Some(LanguageDetector::new()?);
+        }
+        if let Some(selection) = &self.settings.git_selection {
+            self.git_files = Some(git::selected_files(&self.paths, selection)?);
+        }
+        let will_write = !self.settings.dry_run && !self.settings.search_only;
+        if will_write && !self.settings.allow_dirty {
+            // Note: like `cargo fix`, refuse to write files git cannot restore
+            self.dirty_files =
+                git::dirty_files(&self.paths).map_err(|error| Error::UncheckedFiles {
+                    source: Box::new(error),
+                })?;
+        }
+        Ok(())
+    }
+
+    fn patch_file_once(
+        &mut self,
+        seen: &mut HashSet<PathBuf>,
+        path: &Path,
+        query: &Query,
+    ) -> Result<()> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(git_files) = &self.git_files {
+            if !git_files.contains(&key) {
+                return Ok(());
+            }
+        }
+        if seen.insert(key) {
+            self.patch_file(path, query)?;
+        }
+        Ok(())
+    }
+
+    // Apply the same filters as the walker, except for ignore files. Paths
+    // are relative to `root`, like for a walker started from there
+    fn is_listed_file_selected(
+        &self,
+        root: &Path,
+        path: &Path,
+        types: &ignore::types::Types,
+        overrides: &ignore::overrides::Override,
+    ) -> bool {
+        let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let overridden = overrides.matched(&canonical_path, false);
+        if overridden.is_whitelist() {
+            return true;
+        }
+        if overridden.is_ignore() || types.matched(path, false).is_ignore() {
+            return false;
+        }
+        // Note: only look at the components below the root, so that files in
+        // ~/.config/ are not hidden. For files outside the root, only look
+        // at the file name
+        let relative_path = match canonical_path.strip_prefix(root) {
+            Ok(relative_path) => relative_path,
+            Err(_) => Path::new(canonical_path.file_name().unwrap_or_default()),
+        };
+        let is_hidden = relative_path.components().any(|component| match component {
+            std::path::Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+            _ => false,
+        });
+        self.settings.hidden || !is_hidden
+    }
+
     pub fn stats(self) -> Stats {
         self.stats
     }
 
     pub(crate) fn patch_file(&mut self, entry: &Path, query: &Query) -> Result<()> {
-        let file_patcher = FilePatcher::new(entry, &query)?;
-        let file_patcher = match file_patcher {
-            None => return Ok(()),
-            Some(f) => f,
+        let language = self
+            .language_detector
+            .as_ref()
+            .and_then(|detector| detector.detect(entry));
+        let file_patcher = match FilePatcher::new(entry, query, self.settings, language) {
+            Ok(f) => f,
+            // Note: binary files and text files that are not UTF-8 are skipped silently
+            Err(Error::Encoding { .. }) => return Ok(()),
+            Err(err) => {
+                self.report_error(err);
+                return Ok(());
+            }
         };
+        if let Some(reason) = file_patcher.skip_reason() {
+            self.stats.skip_file();
+            if self.settings.output_format == OutputFormat::Lines {
+                println!("{}: skipped, file {}", entry.display(), reason);
+                println!();
+            }
+            return Ok(());
+        }
         let num_replacements = file_patcher.num_replacements();
         if num_replacements != 0 {
-            println!();
+            match self.settings.output_format {
+                OutputFormat::Lines => println!(),
+                OutputFormat::Count => println!("{}:{}", entry.display(), num_replacements),
+                OutputFormat::FilesWithMatches => println!("{}", entry.display()),
+                OutputFormat::FilesOnly => println!("{}:{}", entry.display(), num_replacements),
+                OutputFormat::StatsOnly | OutputFormat::Quiet => (),
+            }
         }
-        let num_lines = file_patcher.num_lines();
-        self.stats.update(num_lines, num_replacements);
-        if self.settings.dry_run {
+        self.stats.update(file_patcher.stats());
+        if num_replacements == 0 || self.settings.dry_run || self.settings.search_only {
             return Ok(());
         }
-        file_patcher.run()?;
+        // Note: when no file is dirty, there is no need to wait for the
+        // other files before writing this one
+        if self.dirty_files.is_empty() {
+            self.write(file_patcher);
+        } else {
+            self.pending.push(file_patcher);
+        }
         Ok(())
     }
 
-    fn build_walker(&self) -> Result<ignore::Walk> {
+    fn report_error(&mut self, error: Error) {
+        self.stats.add_error(error);
+    }
+
+    fn build_walker(&self, path: &Path) -> Result<ignore::Walk> {
+        let mut walk_builder = ignore::WalkBuilder::new(path);
+        walk_builder.types(self.build_types()?);
+        walk_builder.overrides(self.build_overrides(path)?);
+        // Note: the walk_builder configures the "ignore" settings of the Walker,
+        // hence the negations
+        if self.settings.ignored {
+            walk_builder.ignore(false);
+        }
+        if self.settings.hidden {
+            walk_builder.hidden(false);
+        }
+        if !self.settings.no_ruplacerignore {
+            walk_builder.add_custom_ignore_filename(RUPLACERIGNORE_FILE_NAME);
+        }
+        walk_builder
+            .max_depth(self.settings.max_depth)
+            .follow_links(self.settings.follow_links)
+            .same_file_system(self.settings.one_file_system)
+            .max_filesize(self.settings.max_filesize);
+        Ok(walk_builder.build())
+    }
+
+    fn build_types(&self) -> Result<ignore::types::Types> {
         let mut types_builder = ignore::types::TypesBuilder::new();
         types_builder.add_defaults();
         let mut count: u32 = 0;
@@ -107,17 +397,81 @@ impl<'a> DirectoryPatcher<'a> {
                 types_builder.negate(t);
             }
         }
-        let types_matcher = types_builder.build()?;
-        let mut walk_builder = ignore::WalkBuilder::new(&self.path);
-        walk_builder.types(types_matcher);
-        // Note: the walk_builder configures the "ignore" settings of the Walker,
-        // hence the negations
-        if self.settings.ignored {
-            walk_builder.ignore(false);
+        types_builder.build().map_err(Error::InvalidGlob)
+    }
+
+    // Globs are relative to `root`
+    fn build_overrides(&self, root: &Path) -> Result<ignore::overrides::Override> {
+        let mut overrides_builder = ignore::overrides::OverrideBuilder::new(root);
+        for glob in &self.settings.included_globs {
+            overrides_builder.add(glob).map_err(Error::InvalidGlob)?;
         }
-        if self.settings.hidden {
-            walk_builder.hidden(false);
+        for glob in &self.settings.excluded_globs {
+            // Note: in overrides, globs are whitelisted unless they start with '!'
+            overrides_builder
+                .add(&format!("!{}", glob))
+                .map_err(Error::InvalidGlob)?;
         }
-        Ok(walk_builder.build())
+        overrides_builder.build().map_err(Error::InvalidGlob)
+    }
+}
+
+#[derive(Debug)]
+/// Builds a DirectoryPatcher, see `DirectoryPatcher::builder()`
+pub struct DirectoryPatcherBuilder<'a> {
+    paths: Vec<&'a Path>,
+    listed_files: bool,
+    settings: &'a Settings,
+}
+
+impl<'a> DirectoryPatcherBuilder<'a> {
+    /// Add a path to patch. It can be a directory or a file
+    pub fn path(mut self, path: &'a Path) -> Self {
+        self.paths.push(path);
+        self
+    }
+
+    /// Add several paths to patch
+    ///
+    /// ```rust
+    /// use ruplacer::{DirectoryPatcher, Settings};
+    /// use std::path::Path;
+    ///
+    /// let settings = Settings::default();
+    /// let paths = [Path::new("tests/data/a_dir"), Path::new("tests/data/top.txt")];
+    /// let directory_patcher = DirectoryPatcher::builder(&settings).paths(&paths).build();
+    /// ```
+    pub fn paths<P>(mut self, paths: impl IntoIterator<Item = &'a P>) -> Self
+    where
+        P: AsRef<Path> + ?Sized + 'a,
+    {
+        self.paths.extend(paths.into_iter().map(|p| p.as_ref()));
+        self
+    }
+
+    /// If true, the paths are a list of files to patch, without walking any
+    /// directory, like with `DirectoryPatcher::with_files` (default: false)
+    pub fn listed_files(mut self, listed_files: bool) -> Self {
+        self.listed_files = listed_files;
+        self
+    }
+
+    /// Note: nothing is patched if no path was added
+    pub fn build(self) -> DirectoryPatcher<'a> {
+        let mut patcher = DirectoryPatcher::from_paths(self.paths, self.settings);
+        patcher.listed_files = self.listed_files;
+        patcher
+    }
+}
+
+// Return true if the error was caused by a symbolic link pointing to
+// one of its parent directories
+fn is_loop(error: &ignore::Error) -> bool {
+    match error {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
     }
 }
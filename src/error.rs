@@ -0,0 +1,110 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// Errors returned by ruplacer
+///
+/// ```rust
+/// use ruplacer::{DirectoryPatcher, Error, Query, Settings};
+/// use std::path::PathBuf;
+///
+/// let settings = Settings::builder().include(["src/{**"]).build();
+/// let path = PathBuf::from("tests/data");
+/// let mut directory_patcher = DirectoryPatcher::new(&path, &settings);
+/// let query = Query::substring("old", "new");
+/// match directory_patcher.run(&query) {
+///     Err(Error::InvalidGlob(err)) => println!("Please fix the globs: {}", err),
+///     other => panic!("unexpected result: {:?}", other),
+/// }
+/// ```
+pub enum Error {
+    /// A file could not be opened
+    Open { path: PathBuf, source: io::Error },
+    /// A file, or its metadata, could not be read
+    Read { path: PathBuf, source: io::Error },
+    /// A file could not be written
+    Write { path: PathBuf, source: io::Error },
+    /// A directory entry could not be read while walking the source paths
+    Walk(ignore::Error),
+    /// One of the globs or file types in the settings is invalid
+    InvalidGlob(ignore::Error),
+    /// A file is not valid UTF-8. Such files are skipped when
+    /// running a DirectoryPatcher, so this is only used internally for now
+    Encoding { path: PathBuf },
+    /// A source file could not be parsed when restricting the scope
+    Parse { path: PathBuf, message: String },
+    /// A git command failed
+    Git { command: String, message: String },
+    /// Some of the files to patch have uncommitted changes, and
+    /// `Settings::allow_dirty` is false. Nothing was written
+    DirtyFiles { paths: Vec<PathBuf> },
+    /// Some of the files to patch could not be checked for uncommitted
+    /// changes, for instance because they are not in a git repository,
+    /// and `Settings::allow_dirty` is false. Nothing was written
+    UncheckedFiles { source: Box<Error> },
+    /// The settings require a cargo feature ruplacer was built without
+    MissingFeature {
+        feature: &'static str,
+        usage: &'static str,
+    },
+    /// A value could not be parsed, for instance by `Scope::from_str`
+    InvalidValue {
+        value: String,
+        expected: &'static str,
+    },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Open { path, .. } => write!(f, "Could not open {}", path.display()),
+            Error::Read { path, .. } => write!(f, "Could not read {}", path.display()),
+            Error::Write { path, .. } => write!(f, "Could not write {}", path.display()),
+            // Note: the ignore::Error, with the path and the cause, is the source
+            Error::Walk(_) => write!(f, "Could not read directory entry"),
+            Error::InvalidGlob(_) => write!(f, "Invalid glob or file type"),
+            Error::Encoding { path } => write!(f, "{} is not valid UTF-8", path.display()),
+            Error::Parse { path, message } => {
+                write!(f, "Could not parse {}: {}", path.display(), message)
+            }
+            Error::Git { command, message } => write!(f, "`{}` failed: {}", command, message),
+            Error::DirtyFiles { paths } => {
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(
+                    f,
+                    "Some files have uncommitted changes: {}. Commit or stash them first, or set Settings::allow_dirty",
+                    paths.join(", ")
+                )
+            }
+            Error::UncheckedFiles { .. } => write!(
+                f,
+                "Could not check for uncommitted changes. Make sure the files are in a git repository, or set Settings::allow_dirty"
+            ),
+            Error::MissingFeature { feature, usage } => write!(
+                f,
+                "ruplacer must be built with the '{}' feature to {}",
+                feature, usage
+            ),
+            Error::InvalidValue { value, expected } => {
+                write!(f, "'{}' is not a valid choice. {}", value, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Open { source, .. }
+            | Error::Read { source, .. }
+            | Error::Write { source, .. } => Some(source),
+            Error::Walk(err) | Error::InvalidGlob(err) => Some(err),
+            Error::UncheckedFiles { source } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
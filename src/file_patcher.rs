@@ -1,69 +1,425 @@
-use anyhow::{Context, Result};
+use colored::*;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
+use crate::directives::{is_file_disabled, Directives};
+use crate::error::{Error, Result};
+use crate::identifiers::find_identifiers;
+use crate::lexer::{Language, Lexer};
 use crate::query::Query;
 use crate::replace;
+use crate::settings::{DiffStyle, OutputFormat, Scope, Settings};
+use crate::stats::FileStats;
 
+#[derive(Debug)]
 pub struct FilePatcher {
     path: PathBuf,
     new_contents: String,
     num_replacements: usize,
     num_lines: usize,
+    num_skipped: usize,
+    num_bytes_before: usize,
+    // Why the whole file was left untouched, if it contains matches
+    // but the settings exclude it
+    skip_reason: Option<String>,
 }
 
 impl FilePatcher {
-    pub fn new(path: &Path, query: &Query) -> Result<Option<FilePatcher>> {
+    pub fn new(
+        path: &Path,
+        query: &Query,
+        settings: &Settings,
+        language: Option<Language>,
+    ) -> Result<FilePatcher> {
         let mut num_replacements = 0;
         let mut num_lines = 0;
-        let file =
-            File::open(&path).with_context(|| format!("Could not open {}", path.display()))?;
+        let mut num_skipped = 0;
+        let mut selector = MatchSelector::new(settings);
+        let file = File::open(path).map_err(|source| Error::Open {
+            path: path.to_path_buf(),
+            source,
+        })?;
         let reader = BufReader::new(file);
-        let mut new_contents = String::new();
         // Note: not using lines() because we need to preserve the line endings
         // when writing the file later on
-        for (num, chunk) in LineIterator::new(b'\n', reader).enumerate() {
-            let chunk = chunk.with_context(|| format!("Error while reading {}", path.display()))?;
-            let line = std::str::from_utf8(&chunk);
-            if line.is_err() {
-                return Ok(None);
-            }
-            let line = line.unwrap();
-            let replacement = replace(&line, &query);
+        let mut lines = vec![];
+        for chunk in LineIterator::new(b'\n', reader) {
+            let chunk = chunk.map_err(|source| Error::Read {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            let line = String::from_utf8(chunk).map_err(|_| Error::Encoding {
+                path: path.to_path_buf(),
+            })?;
+            lines.push(line);
+        }
+        if let Some(reason) = skip_reason(&lines, settings, language) {
+            // Only report files that would have been patched otherwise
+            let has_matches = lines.iter().any(|line| replace(line, query).is_some());
+            let contents = lines.concat();
+            return Ok(FilePatcher {
+                path: path.to_path_buf(),
+                num_lines: 0,
+                num_replacements: 0,
+                num_skipped: 0,
+                num_bytes_before: contents.len(),
+                new_contents: contents,
+                skip_reason: if has_matches { Some(reason) } else { None },
+            });
+        }
+        let file_disabled = is_file_disabled(lines.iter().map(|l| l.as_str()));
+        let mut directives = Directives::new();
+        let mut new_contents = String::new();
+        let regions_by_line =
+            split_lines(&lines, settings.scope, language).map_err(|message| Error::Parse {
+                path: path.to_path_buf(),
+                message,
+            })?;
+        let print_lines = settings.output_format == OutputFormat::Lines;
+        let mut context = ContextTracker::new(settings);
+        // Note: align context lines with the - and + of the diff output
+        let indent = if settings.search_only || settings.diff_style != DiffStyle::Lines {
+            ""
+        } else {
+            "  "
+        };
+        let width = terminal_width();
+        for (num, line) in lines.iter().enumerate() {
+            let line = line.as_str();
+            let lineno = num + 1;
+            let regions = regions_by_line.get(num).map_or(&[][..], |r| r.as_slice());
+            let is_skipped = file_disabled || directives.is_disabled(line);
+            let replacement = if is_skipped || !is_line_selected(line, settings) {
+                if let Some(replacement) = replace(line, query) {
+                    num_skipped += replacement.num_fragments();
+                }
+                None
+            } else {
+                replace(line, query).and_then(|replacement| {
+                    let num_matches = replacement.num_fragments();
+                    let replacement = replacement.retain_fragments(|range| {
+                        is_in_scope(regions, settings.scope, &range) && selector.keep(lineno)
+                    });
+                    let num_kept = replacement.as_ref().map_or(0, |r| r.num_fragments());
+                    num_skipped += num_matches - num_kept;
+                    replacement
+                })
+            };
             match replacement {
-                None => new_contents.push_str(&line),
-                Some(replacement) => {
+                None => {
+                    if print_lines && context.is_after_match(lineno) {
+                        print_context_line(path, lineno, indent, line, settings.max_columns);
+                    }
+                    new_contents.push_str(line)
+                }
+                Some(mut replacement) => {
+                    replacement.set_max_columns(settings.max_columns);
                     num_lines += 1;
                     num_replacements += replacement.num_fragments();
-                    let lineno = num + 1;
                     let prefix = format!("{}:{} ", path.display(), lineno);
                     let new_line = replacement.output();
-                    replacement.print_self(&prefix);
-                    new_contents.push_str(&new_line);
+                    if print_lines {
+                        let (separator, before) = context.before_match(lineno);
+                        if separator {
+                            println!("{}", "--".dimmed());
+                        }
+                        for context_lineno in before {
+                            let context_line = &lines[context_lineno - 1];
+                            print_context_line(
+                                path,
+                                context_lineno,
+                                indent,
+                                context_line,
+                                settings.max_columns,
+                            );
+                        }
+                        match settings.diff_style {
+                            _ if settings.search_only => replacement.print_match(&prefix),
+                            DiffStyle::Lines => replacement.print_self(&prefix),
+                            DiffStyle::Word => replacement.print_word_diff(&prefix),
+                            DiffStyle::SideBySide => replacement.print_side_by_side(&prefix, width),
+                        }
+                    }
+                    new_contents.push_str(new_line);
                 }
             }
         }
-        Ok(Some(FilePatcher {
+        Ok(FilePatcher {
             path: path.to_path_buf(),
             new_contents,
             num_lines,
             num_replacements,
-        }))
+            num_skipped,
+            num_bytes_before: lines.iter().map(|line| line.len()).sum(),
+            skip_reason: None,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
     }
 
     pub(crate) fn num_replacements(&self) -> usize {
         self.num_replacements
     }
 
-    pub(crate) fn num_lines(&self) -> usize {
-        self.num_lines
+    /// Why the file was skipped, if it contains matches but the settings
+    /// exclude it as a whole
+    pub(crate) fn skip_reason(&self) -> Option<&str> {
+        self.skip_reason.as_deref()
+    }
+
+    pub(crate) fn stats(&self) -> FileStats {
+        FileStats {
+            path: self.path.clone(),
+            lines: self.num_lines,
+            replacements: self.num_replacements,
+            skipped_matches: self.num_skipped,
+            bytes_before: self.num_bytes_before,
+            bytes_after: self.new_contents.len(),
+        }
     }
 
     pub fn run(&self) -> Result<()> {
-        std::fs::write(&self.path, &self.new_contents)
-            .with_context(|| format!("Could not write {}", self.path.display()))?;
-        Ok(())
+        std::fs::write(&self.path, &self.new_contents).map_err(|source| Error::Write {
+            path: self.path.clone(),
+            source,
+        })
+    }
+}
+
+// Evaluate the if_contains and unless_contains settings, and make sure
+// the scope can be restricted if needed.
+// Return the reason why the file should be left untouched, if any
+fn skip_reason(
+    lines: &[String],
+    settings: &Settings,
+    language: Option<Language>,
+) -> Option<String> {
+    let unknown_language = settings.scope != Scope::All && language.is_none();
+    if settings.if_contains.is_none() && settings.unless_contains.is_none() && !unknown_language {
+        return None;
+    }
+    let contents = lines.concat();
+    let mut reason = None;
+    if unknown_language {
+        reason = Some("is not written in a known language".to_string());
+    }
+    if let Some(regex) = &settings.if_contains {
+        if !regex.is_match(&contents) {
+            reason = Some(format!("does not contain '{}'", regex));
+        }
+    }
+    if let Some(regex) = &settings.unless_contains {
+        if regex.is_match(&contents) {
+            reason = Some(format!("contains '{}'", regex));
+        }
+    }
+    reason
+}
+
+// Evaluate the only_lines and skip_lines settings. The line ending is
+// not part of the line, so that patterns such as 'foo$' work
+fn is_line_selected(line: &str, settings: &Settings) -> bool {
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    if let Some(regex) = &settings.only_lines {
+        if !regex.is_match(line) {
+            return false;
+        }
+    }
+    if let Some(regex) = &settings.skip_lines {
+        if regex.is_match(line) {
+            return false;
+        }
+    }
+    true
+}
+
+type Regions = Vec<(Range<usize>, Scope)>;
+
+// Return the regions of each line, with byte ranges relative to
+// the start of the line. Return an empty list if the scope is not restricted
+fn split_lines(
+    lines: &[String],
+    scope: Scope,
+    language: Option<Language>,
+) -> std::result::Result<Vec<Regions>, String> {
+    let language = match (scope, language) {
+        (Scope::All, _) | (_, None) => return Ok(vec![]),
+        (_, Some(language)) => language,
+    };
+    if scope != Scope::Identifiers {
+        // Note: the lexer must see every line to keep track of
+        // multiline comments and strings
+        let mut lexer = Lexer::new(language);
+        return Ok(lines.iter().map(|line| lexer.split_line(line)).collect());
+    }
+
+    let source = lines.concat();
+    let mut identifiers = find_identifiers(language, &source)?.into_iter().peekable();
+    let mut res = vec![];
+    let mut line_start = 0;
+    for line in lines {
+        let line_end = line_start + line.len();
+        let mut regions = vec![];
+        while let Some(identifier) = identifiers.next_if(|i| i.start < line_end) {
+            let start = identifier.start - line_start;
+            let end = identifier.end.min(line_end) - line_start;
+            regions.push((start..end, Scope::Identifiers));
+        }
+        res.push(regions);
+        line_start = line_end;
+    }
+    Ok(res)
+}
+
+// Return true if the match at `range` is fully contained in a region of the
+// given scope. When only patching identifiers, the match must cover the
+// whole identifier
+fn is_in_scope(regions: &[(Range<usize>, Scope)], scope: Scope, range: &Range<usize>) -> bool {
+    if scope == Scope::All {
+        return true;
+    }
+    if scope == Scope::Identifiers {
+        return regions.iter().any(|(region, _)| region == range);
+    }
+    regions.iter().any(|(region, region_scope)| {
+        *region_scope == scope && region.start <= range.start && range.end <= region.end
+    })
+}
+
+/// `MatchSelector` decides which matches should be replaced, according
+/// to the line ranges, occurrences and limits found in the settings.
+struct MatchSelector<'a> {
+    settings: &'a Settings,
+    // Number of matches seen so far in the selected lines
+    num_seen: usize,
+    num_kept: usize,
+    current_lineno: usize,
+    num_seen_in_line: usize,
+}
+
+impl<'a> MatchSelector<'a> {
+    fn new(settings: &'a Settings) -> Self {
+        Self {
+            settings,
+            num_seen: 0,
+            num_kept: 0,
+            current_lineno: 0,
+            num_seen_in_line: 0,
+        }
+    }
+
+    /// Called for each match, in order
+    fn keep(&mut self, lineno: usize) -> bool {
+        let settings = self.settings;
+        let line_ranges = &settings.line_ranges;
+        if !line_ranges.is_empty() && !line_ranges.iter().any(|r| r.contains(&lineno)) {
+            return false;
+        }
+        if lineno != self.current_lineno {
+            self.current_lineno = lineno;
+            self.num_seen_in_line = 0;
+        }
+        self.num_seen_in_line += 1;
+        if let Some(max) = settings.max_replacements_per_line {
+            if self.num_seen_in_line > max {
+                return false;
+            }
+        }
+        self.num_seen += 1;
+        if !settings.occurrences.is_empty() && !settings.occurrences.contains(&self.num_seen) {
+            return false;
+        }
+        if let Some(max) = settings.max_replacements_per_file {
+            if self.num_kept >= max {
+                return false;
+            }
+        }
+        self.num_kept += 1;
+        true
+    }
+}
+
+// Used when stdout is not a terminal
+const DEFAULT_TERMINAL_WIDTH: usize = 120;
+
+fn terminal_width() -> usize {
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), _)) => width as usize,
+        None => DEFAULT_TERMINAL_WIDTH,
+    }
+}
+
+fn print_context_line(
+    path: &Path,
+    lineno: usize,
+    indent: &str,
+    line: &str,
+    max_columns: Option<usize>,
+) {
+    let mut line = line
+        .trim_start()
+        .trim_end_matches(&['\r', '\n'][..])
+        .to_string();
+    // Context lines contain no match, so simply cut them
+    if let Some(max_columns) = max_columns {
+        if line.chars().count() > max_columns {
+            line = line.chars().take(max_columns.saturating_sub(1)).collect();
+            line.push('…');
+        }
+    }
+    let context_line = format!("{}:{} {}{}", path.display(), lineno, indent, line);
+    println!("{}", context_line.dimmed());
+}
+
+/// `ContextTracker` decides which lines should be printed around each
+/// matching line, merging the hunks that overlap or touch each other,
+/// like `grep` does.
+struct ContextTracker {
+    before: usize,
+    after: usize,
+    last_printed: Option<usize>,
+    num_after_left: usize,
+}
+
+impl ContextTracker {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            before: settings.context_before,
+            after: settings.context_after,
+            last_printed: None,
+            num_after_left: 0,
+        }
+    }
+
+    /// Called for each matching line, in order. Return whether a separator
+    /// should be printed, followed by the numbers of the lines to print
+    /// before the matching line
+    fn before_match(&mut self, lineno: usize) -> (bool, Range<usize>) {
+        let mut start = lineno.saturating_sub(self.before).max(1);
+        let mut separator = false;
+        if let Some(last_printed) = self.last_printed {
+            start = start.max(last_printed + 1);
+            let has_context = self.before != 0 || self.after != 0;
+            separator = has_context && start > last_printed + 1;
+        }
+        self.last_printed = Some(lineno);
+        self.num_after_left = self.after;
+        (separator, start..lineno)
+    }
+
+    /// Called for each other line, in order. Return true if the line
+    /// should be printed after the previous matching line
+    fn is_after_match(&mut self, lineno: usize) -> bool {
+        if self.num_after_left == 0 {
+            return false;
+        }
+        self.num_after_left -= 1;
+        self.last_printed = Some(lineno);
+        true
     }
 }
 
@@ -96,18 +452,19 @@ impl<T: BufRead> Iterator for LineIterator<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Query;
+    use crate::{Query, Settings};
     use std::fs;
 
     #[test]
     fn test_patch_file() {
         let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let settings = Settings::default();
 
         let file_path = temp_dir.path().join("without-trailing-newline.txt");
         fs::write(&file_path, "first line\nI say: old is nice\nlast line").unwrap();
         let query = Query::substring("old", "new");
-        let file_patcher = FilePatcher::new(&file_path, &query).unwrap();
-        file_patcher.unwrap().run().unwrap();
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        file_patcher.run().unwrap();
         let actual = fs::read_to_string(&file_path).unwrap();
         let expected = "first line\nI say: new is nice\nlast line";
         assert_eq!(actual, expected);
@@ -115,10 +472,193 @@ mod tests {
         let file_path = temp_dir.path().join("with-trailing-newline.txt");
         fs::write(&file_path, "first line\nI say: old is nice\nlast line\n").unwrap();
         let query = Query::substring("old", "new");
-        let file_patcher = FilePatcher::new(&file_path, &query).unwrap();
-        file_patcher.unwrap().run().unwrap();
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        file_patcher.run().unwrap();
         let actual = fs::read_to_string(&file_path).unwrap();
         let expected = "first line\nI say: new is nice\nlast line\n";
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_context_lines() {
+        let settings = Settings {
+            context_before: 2,
+            context_after: 1,
+            ..Default::default()
+        };
+        let mut context = ContextTracker::new(&settings);
+        let matches = [3, 5, 10];
+        let mut actual = vec![];
+        for lineno in 1..=12 {
+            if matches.contains(&lineno) {
+                let (separator, before) = context.before_match(lineno);
+                if separator {
+                    actual.push("--".to_string());
+                }
+                actual.extend(before.map(|l| l.to_string()));
+                actual.push(format!("{}*", lineno));
+            } else if context.is_after_match(lineno) {
+                actual.push(lineno.to_string());
+            }
+        }
+        let expected = ["1", "2", "3*", "4", "5*", "6", "--", "8", "9", "10*", "11"];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_select_matches() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let file_path = temp_dir.path().join("foo.txt");
+        let contents = "old old old\nold old\nold\n";
+        let query = Query::substring("old", "new");
+
+        let check = |settings: &Settings, expected: &str, expected_skipped: usize| {
+            fs::write(&file_path, contents).unwrap();
+            let file_patcher = FilePatcher::new(&file_path, &query, settings, None).unwrap();
+            assert_eq!(file_patcher.stats().skipped_matches(), expected_skipped);
+            file_patcher.run().unwrap();
+            let actual = fs::read_to_string(&file_path).unwrap();
+            assert_eq!(actual, expected);
+        };
+
+        let settings = Settings {
+            max_replacements_per_file: Some(2),
+            ..Default::default()
+        };
+        check(&settings, "new new old\nold old\nold\n", 4);
+
+        let settings = Settings {
+            max_replacements_per_line: Some(1),
+            ..Default::default()
+        };
+        check(&settings, "new old old\nnew old\nnew\n", 3);
+
+        let settings = Settings {
+            occurrences: vec![2, 4],
+            ..Default::default()
+        };
+        check(&settings, "old new old\nnew old\nold\n", 4);
+
+        let settings = Settings {
+            line_ranges: vec![2..=3],
+            occurrences: vec![1],
+            ..Default::default()
+        };
+        check(&settings, "old old old\nnew old\nold\n", 5);
+    }
+
+    #[test]
+    fn test_line_filters() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let file_path = temp_dir.path().join("foo.rs");
+        let contents = "let old = 1;\nlet old = 2; // keep\nold();\n";
+        let query = Query::substring("old", "new");
+
+        let check = |settings: &Settings, expected: &str, expected_skipped: usize| {
+            fs::write(&file_path, contents).unwrap();
+            let file_patcher = FilePatcher::new(&file_path, &query, settings, None).unwrap();
+            assert_eq!(file_patcher.stats().skipped_matches(), expected_skipped);
+            file_patcher.run().unwrap();
+            let actual = fs::read_to_string(&file_path).unwrap();
+            assert_eq!(actual, expected);
+        };
+
+        let settings = Settings {
+            skip_lines: Some(regex::Regex::new("// keep").unwrap()),
+            ..Default::default()
+        };
+        check(&settings, "let new = 1;\nlet old = 2; // keep\nnew();\n", 1);
+
+        let settings = Settings {
+            only_lines: Some(regex::Regex::new("^let").unwrap()),
+            skip_lines: Some(regex::Regex::new("// keep").unwrap()),
+            ..Default::default()
+        };
+        check(&settings, "let new = 1;\nlet old = 2; // keep\nold();\n", 2);
+
+        // The line ending is not part of the line
+        let settings = Settings {
+            only_lines: Some(regex::Regex::new(";$").unwrap()),
+            ..Default::default()
+        };
+        check(&settings, "let new = 1;\nlet old = 2; // keep\nnew();\n", 1);
+
+        fs::write(&file_path, "old;\r\nold\r\n").unwrap();
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        file_patcher.run().unwrap();
+        let actual = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(actual, "new;\r\nold\r\n");
+    }
+
+    #[test]
+    fn test_directives() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let settings = Settings::default();
+        let query = Query::substring("old", "new");
+
+        let file_path = temp_dir.path().join("foo.py");
+        fs::write(
+            &file_path,
+            "old\n# ruplacer: disable-next-line\nold old\nold\n",
+        )
+        .unwrap();
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        assert_eq!(file_patcher.num_replacements(), 2);
+        assert_eq!(file_patcher.stats().skipped_matches(), 2);
+        file_patcher.run().unwrap();
+        let actual = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(actual, "new\n# ruplacer: disable-next-line\nold old\nnew\n");
+
+        let file_path = temp_dir.path().join("bar.py");
+        fs::write(&file_path, "old\n# ruplacer: disable-file\nold\n").unwrap();
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        assert_eq!(file_patcher.num_replacements(), 0);
+        assert_eq!(file_patcher.stats().skipped_matches(), 2);
+    }
+
+    #[test]
+    fn test_guards() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let file_path = temp_dir.path().join("foo.txt");
+        fs::write(&file_path, "old\n").unwrap();
+        let query = Query::substring("old", "new");
+
+        let settings = Settings {
+            if_contains: Some(regex::Regex::new("spam").unwrap()),
+            ..Default::default()
+        };
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        assert_eq!(file_patcher.skip_reason(), Some("does not contain 'spam'"));
+        assert_eq!(file_patcher.num_replacements(), 0);
+        file_patcher.run().unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "old\n");
+
+        let settings = Settings {
+            unless_contains: Some(regex::Regex::new("ol.").unwrap()),
+            ..Default::default()
+        };
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        assert_eq!(file_patcher.skip_reason(), Some("contains 'ol.'"));
+
+        // Files without any match are not reported as skipped
+        let query = Query::substring("eggs", "spam");
+        let file_patcher = FilePatcher::new(&file_path, &query, &settings, None).unwrap();
+        assert_eq!(file_patcher.skip_reason(), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let temp_dir = tempdir::TempDir::new("test-ruplacer").unwrap();
+        let settings = Settings::default();
+        let query = Query::substring("old", "new");
+
+        let file_path = temp_dir.path().join("missing.txt");
+        let result = FilePatcher::new(&file_path, &query, &settings, None);
+        assert!(matches!(result, Err(Error::Open { path, .. }) if path == file_path));
+
+        let file_path = temp_dir.path().join("latin1.txt");
+        fs::write(&file_path, b"old caf\xe9\n").unwrap();
+        let result = FilePatcher::new(&file_path, &query, &settings, None);
+        assert!(matches!(result, Err(Error::Encoding { path }) if path == file_path));
+    }
 }
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{Error, Result};
+use crate::settings::GitSelection;
+
+/// Run git in the given directory, and return its standard output
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let git_error = |message: String| Error::Git {
+        command: format!("git {}", args.join(" ")),
+        message: format!("{} (in {})", message, dir.display()),
+    };
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .map_err(|err| git_error(format!("could not run git: {}", err)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(git_error(stderr.trim().to_string()));
+    }
+    String::from_utf8(output.stdout).map_err(|_| git_error("output is not valid UTF-8".into()))
+}
+
+/// Return the directory git should be run from to look at `path`
+fn git_dir(path: &Path) -> &Path {
+    if path.is_dir() {
+        return path;
+    }
+    match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Return the absolute path of the top level directory of the git
+/// repository containing `path`
+pub(crate) fn top_level(path: &Path) -> Result<PathBuf> {
+    let output = run_git(git_dir(path), &["rev-parse", "--show-toplevel"])?;
+    let top_level = PathBuf::from(output.trim_end());
+    // Note: canonicalize so that paths can be compared with the ones
+    // found by the walker
+    Ok(top_level.canonicalize().unwrap_or(top_level))
+}
+
+/// Return the files with uncommitted changes, including untracked files,
+/// in the repositories containing `paths`
+pub(crate) fn dirty_files(paths: &[&Path]) -> Result<HashSet<PathBuf>> {
+    let dirs: HashSet<_> = paths.iter().map(|path| git_dir(path)).collect();
+    let top_levels = dirs
+        .into_iter()
+        .map(top_level)
+        .collect::<Result<HashSet<_>>>()?;
+    let mut files = HashSet::new();
+    for top_level in top_levels {
+        let output = run_git(
+            &top_level,
+            &["status", "--porcelain", "-z", "--untracked-files=all"],
+        )?;
+        // Each entry looks like "XY path". Renames and copies are followed
+        // by an other entry containing the original path
+        let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+        while let Some(entry) = entries.next() {
+            let (status, name) = match (entry.get(..2), entry.get(3..)) {
+                (Some(status), Some(name)) => (status, name),
+                _ => continue,
+            };
+            files.insert(top_level.join(name));
+            if status.starts_with(['R', 'C']) {
+                entries.next();
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Return the canonical paths of the files selected by git in the
+/// repositories containing `paths`
+pub(crate) fn selected_files(
+    paths: &[&Path],
+    selection: &GitSelection,
+) -> Result<HashSet<PathBuf>> {
+    // Note: there can be many paths when they come from a list of files,
+    // so only run git once per directory, and once per repository
+    let dirs: HashSet<_> = paths.iter().map(|path| git_dir(path)).collect();
+    let top_levels = dirs
+        .into_iter()
+        .map(top_level)
+        .collect::<Result<HashSet<_>>>()?;
+    let mut files = HashSet::new();
+    for top_level in top_levels {
+        files.extend(files_in_repository(&top_level, selection)?);
+    }
+    Ok(files)
+}
+
+fn files_in_repository(top_level: &Path, selection: &GitSelection) -> Result<Vec<PathBuf>> {
+    // Note: always use paths relative to the top level directory, and skip
+    // deleted files, as there is nothing left to patch in them
+    let output = match selection {
+        GitSelection::Tracked => run_git(top_level, &["ls-files", "-z"])?,
+        GitSelection::ChangedSince(revision) => run_git(
+            top_level,
+            &[
+                "diff",
+                "--name-only",
+                "-z",
+                "--diff-filter=d",
+                revision,
+                "--",
+            ],
+        )?,
+        GitSelection::Staged => run_git(
+            top_level,
+            &["diff", "--name-only", "-z", "--diff-filter=d", "--cached"],
+        )?,
+    };
+    Ok(output
+        .split('\0')
+        .filter(|name| !name.is_empty())
+        .map(|name| top_level.join(name))
+        .collect())
+}
@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+use crate::error::Error;
+use crate::lexer::Language;
+
+/// Returned when trying to only patch identifiers without the tree-sitter feature
+pub(crate) fn missing_feature_error() -> Error {
+    Error::MissingFeature {
+        feature: "tree-sitter",
+        usage: "only patch identifiers",
+    }
+}
+
+/// Return the byte ranges of every identifier found in `source`,
+/// in order, or a message explaining why `source` could not be parsed
+#[cfg(feature = "tree-sitter")]
+pub(crate) fn find_identifiers(
+    language: Language,
+    source: &str,
+) -> Result<Vec<Range<usize>>, String> {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&grammar(language))
+        .map_err(|err| err.to_string())?;
+    let tree = parser
+        .parse(source, None)
+        .ok_or_else(|| "tree-sitter could not parse the file".to_string())?;
+    // Note: tree-sitter recovers from syntax errors, but the identifiers
+    // found around them cannot be trusted
+    if let Some(error) = first_error(tree.root_node()) {
+        return Err(format!(
+            "syntax error at line {}",
+            error.start_position().row + 1
+        ));
+    }
+
+    // Walk the whole tree, depth first
+    let mut identifiers = vec![];
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        // Note: nodes like Rust's scoped_identifier (`Foo::new`) contain
+        // other identifiers, so only look at leaves
+        if node.child_count() == 0 && is_identifier(node.kind()) {
+            identifiers.push(node.byte_range());
+        }
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        loop {
+            if !cursor.goto_parent() {
+                return Ok(identifiers);
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tree-sitter")]
+fn first_error(node: tree_sitter::Node<'_>) -> Option<tree_sitter::Node<'_>> {
+    if node.is_error() || node.is_missing() {
+        return Some(node);
+    }
+    if !node.has_error() {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.children(&mut cursor).collect();
+    children.into_iter().find_map(first_error).or(Some(node))
+}
+
+#[cfg(not(feature = "tree-sitter"))]
+pub(crate) fn find_identifiers(
+    _language: Language,
+    _source: &str,
+) -> Result<Vec<Range<usize>>, String> {
+    Err(missing_feature_error().to_string())
+}
+
+#[cfg(feature = "tree-sitter")]
+fn grammar(language: Language) -> tree_sitter::Language {
+    match language {
+        Language::C => tree_sitter_c::LANGUAGE.into(),
+        Language::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+        Language::Go => tree_sitter_go::LANGUAGE.into(),
+        Language::Java => tree_sitter_java::LANGUAGE.into(),
+        Language::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+        Language::Python => tree_sitter_python::LANGUAGE.into(),
+        Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+        Language::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+        Language::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+    }
+}
+
+// Grammars use "identifier" and "<something>_identifier" for
+// variables, types, fields, properties, and so on
+#[cfg(feature = "tree-sitter")]
+fn is_identifier(kind: &str) -> bool {
+    kind == "identifier" || kind.ends_with("_identifier")
+}
+
+#[cfg(all(test, feature = "tree-sitter"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_identifiers() {
+        let source = "let foo_bar = foo(\"foo\"); // foo\nfoo.bar = Foo::new();\n";
+        let identifiers: Vec<_> = find_identifiers(Language::Rust, source)
+            .unwrap()
+            .into_iter()
+            .map(|range| &source[range])
+            .collect();
+        assert_eq!(
+            identifiers,
+            vec!["foo_bar", "foo", "foo", "bar", "Foo", "new"]
+        );
+    }
+
+    #[test]
+    fn test_typescript_type_assertions() {
+        let source = "const y = <Foo>old;\nconst z = old + 1;\n";
+        let identifiers: Vec<_> = find_identifiers(Language::TypeScript, source)
+            .unwrap()
+            .into_iter()
+            .map(|range| &source[range])
+            .collect();
+        assert_eq!(identifiers, vec!["y", "Foo", "old", "z", "old"]);
+
+        // This is an unclosed JSX element in a .tsx file
+        let err = find_identifiers(Language::Tsx, source).unwrap_err();
+        assert!(err.contains("syntax error"), "{}", err);
+    }
+}
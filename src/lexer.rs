@@ -0,0 +1,545 @@
+use crate::error::{Error, Result};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::settings::Scope;
+
+/// Languages ruplacer knows how to split into code, comments and strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Language {
+    C,
+    Cpp,
+    Go,
+    Java,
+    JavaScript,
+    Python,
+    Rust,
+    TypeScript,
+    // TypeScript with JSX, in .tsx files
+    Tsx,
+}
+
+// Names of the file types from `ignore::types`, and the corresponding language
+const FILE_TYPES: &[(&str, Language)] = &[
+    ("c", Language::C),
+    ("cpp", Language::Cpp),
+    ("go", Language::Go),
+    ("java", Language::Java),
+    ("js", Language::JavaScript),
+    ("ts", Language::TypeScript),
+    ("py", Language::Python),
+    ("rust", Language::Rust),
+];
+
+struct StringDelimiter {
+    start: &'static str,
+    end: &'static str,
+    // Whether a backslash escapes the next character
+    escapes: bool,
+    // Whether the literal can span several lines
+    multiline: bool,
+}
+
+impl StringDelimiter {
+    const fn new(start: &'static str, end: &'static str) -> Self {
+        Self {
+            start,
+            end,
+            escapes: true,
+            multiline: false,
+        }
+    }
+
+    const fn multiline(mut self) -> Self {
+        self.multiline = true;
+        self
+    }
+
+    const fn raw(mut self) -> Self {
+        self.escapes = false;
+        self
+    }
+}
+
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    // Note: when a delimiter is a prefix of an other one, the longest
+    // must come first
+    strings: &'static [StringDelimiter],
+    // Whether to handle Rust char literals and raw strings, which cannot
+    // be described with a StringDelimiter
+    rust_literals: bool,
+}
+
+const C_STRINGS: &[StringDelimiter] = &[
+    StringDelimiter::new("\"", "\""),
+    StringDelimiter::new("'", "'"),
+];
+
+const GO_STRINGS: &[StringDelimiter] = &[
+    StringDelimiter::new("\"", "\""),
+    StringDelimiter::new("'", "'"),
+    StringDelimiter::new("`", "`").raw().multiline(),
+];
+
+const JAVA_STRINGS: &[StringDelimiter] = &[
+    StringDelimiter::new("\"\"\"", "\"\"\"").multiline(),
+    StringDelimiter::new("\"", "\""),
+    StringDelimiter::new("'", "'"),
+];
+
+const JAVASCRIPT_STRINGS: &[StringDelimiter] = &[
+    StringDelimiter::new("\"", "\""),
+    StringDelimiter::new("'", "'"),
+    StringDelimiter::new("`", "`").multiline(),
+];
+
+const PYTHON_STRINGS: &[StringDelimiter] = &[
+    StringDelimiter::new("\"\"\"", "\"\"\"").multiline(),
+    StringDelimiter::new("'''", "'''").multiline(),
+    StringDelimiter::new("\"", "\""),
+    StringDelimiter::new("'", "'"),
+];
+
+const RUST_STRINGS: &[StringDelimiter] = &[StringDelimiter::new("\"", "\"").multiline()];
+
+impl Language {
+    fn syntax(self) -> Syntax {
+        match self {
+            Language::C | Language::Cpp => Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                strings: C_STRINGS,
+                rust_literals: false,
+            },
+            Language::Go => Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                strings: GO_STRINGS,
+                rust_literals: false,
+            },
+            Language::Java => Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                strings: JAVA_STRINGS,
+                rust_literals: false,
+            },
+            Language::JavaScript | Language::TypeScript | Language::Tsx => Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                strings: JAVASCRIPT_STRINGS,
+                rust_literals: false,
+            },
+            Language::Python => Syntax {
+                line_comments: &["#"],
+                block_comment: None,
+                strings: PYTHON_STRINGS,
+                rust_literals: false,
+            },
+            // Note: single quotes are also used for lifetimes in Rust,
+            // so char literals are handled separately
+            Language::Rust => Syntax {
+                line_comments: &["//"],
+                block_comment: Some(("/*", "*/")),
+                strings: RUST_STRINGS,
+                rust_literals: true,
+            },
+        }
+    }
+}
+
+/// Find the language of a file, using the file types from the `ignore` crate
+#[derive(Debug)]
+pub(crate) struct LanguageDetector {
+    types: ignore::types::Types,
+}
+
+impl LanguageDetector {
+    pub(crate) fn new() -> Result<Self> {
+        let mut types_builder = ignore::types::TypesBuilder::new();
+        types_builder.add_defaults();
+        for (name, _) in FILE_TYPES {
+            types_builder.select(name);
+        }
+        let types = types_builder.build().map_err(Error::InvalidGlob)?;
+        Ok(Self { types })
+    }
+
+    pub(crate) fn detect(&self, path: &Path) -> Option<Language> {
+        let matched = self.types.matched(path, false);
+        let name = matched.inner()?.file_type_def()?.name();
+        let language = FILE_TYPES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, language)| *language)?;
+        // Note: the "ts" file type covers both .ts and .tsx files, but they
+        // need different grammars: `<Foo>bar` is a type assertion in the former,
+        // and a JSX element in the latter
+        let is_tsx = path.extension() == Some(std::ffi::OsStr::new("tsx"));
+        if language == Language::TypeScript && is_tsx {
+            return Some(Language::Tsx);
+        }
+        Some(language)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    BlockComment,
+    // Index of the delimiter in Syntax::strings
+    String(usize),
+    // Number of '#' around a Rust raw string
+    RawString(usize),
+}
+
+impl State {
+    fn scope(self) -> Scope {
+        match self {
+            State::Code => Scope::Code,
+            State::BlockComment => Scope::Comments,
+            State::String(_) | State::RawString(_) => Scope::Strings,
+        }
+    }
+}
+
+/// A lightweight lexer splitting lines into code, comments and strings.
+/// It must be given every line of a file, in order, so that block comments
+/// and multiline strings are properly tracked.
+pub(crate) struct Lexer {
+    syntax: Syntax,
+    state: State,
+}
+
+impl Lexer {
+    pub(crate) fn new(language: Language) -> Self {
+        Self {
+            syntax: language.syntax(),
+            state: State::Code,
+        }
+    }
+
+    /// Return the byte ranges of the line, along with their scope
+    pub(crate) fn split_line(&mut self, line: &str) -> Vec<(Range<usize>, Scope)> {
+        let bytes = line.as_bytes();
+        let mut regions = vec![];
+        let mut start = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            let rest = &bytes[i..];
+            match self.state {
+                State::Code => {
+                    if self
+                        .syntax
+                        .line_comments
+                        .iter()
+                        .any(|c| rest.starts_with(c.as_bytes()))
+                    {
+                        // Line comments end with the line
+                        push_region(&mut regions, start..i, Scope::Code);
+                        push_region(&mut regions, i..bytes.len(), Scope::Comments);
+                        start = bytes.len();
+                        break;
+                    }
+                    if let Some((comment_start, _)) = self.syntax.block_comment {
+                        if rest.starts_with(comment_start.as_bytes()) {
+                            push_region(&mut regions, start..i, Scope::Code);
+                            start = i;
+                            i += comment_start.len();
+                            self.state = State::BlockComment;
+                            continue;
+                        }
+                    }
+                    if self.syntax.rust_literals {
+                        if let Some((prefix_len, hashes)) = raw_string_start(bytes, i) {
+                            push_region(&mut regions, start..i, Scope::Code);
+                            start = i;
+                            i += prefix_len;
+                            self.state = State::RawString(hashes);
+                            continue;
+                        }
+                        // Note: i may not be a char boundary, so check the quote first
+                        let char_literal = match rest[0] {
+                            b'\'' => char_literal_len(&line[i..]),
+                            _ => None,
+                        };
+                        if let Some(len) = char_literal {
+                            push_region(&mut regions, start..i, Scope::Code);
+                            push_region(&mut regions, i..i + len, Scope::Strings);
+                            i += len;
+                            start = i;
+                            continue;
+                        }
+                    }
+                    let delimiter = self
+                        .syntax
+                        .strings
+                        .iter()
+                        .position(|d| rest.starts_with(d.start.as_bytes()));
+                    if let Some(index) = delimiter {
+                        push_region(&mut regions, start..i, Scope::Code);
+                        start = i;
+                        i += self.syntax.strings[index].start.len();
+                        self.state = State::String(index);
+                        continue;
+                    }
+                    i += 1;
+                }
+                State::BlockComment => {
+                    let (_, comment_end) = self.syntax.block_comment.unwrap_or_default();
+                    if rest.starts_with(comment_end.as_bytes()) {
+                        i += comment_end.len();
+                        push_region(&mut regions, start..i, Scope::Comments);
+                        start = i;
+                        self.state = State::Code;
+                        continue;
+                    }
+                    i += 1;
+                }
+                State::String(index) => {
+                    let delimiter = &self.syntax.strings[index];
+                    if delimiter.escapes && rest[0] == b'\\' {
+                        i += 2;
+                        continue;
+                    }
+                    if rest.starts_with(delimiter.end.as_bytes()) {
+                        i += delimiter.end.len();
+                        push_region(&mut regions, start..i, Scope::Strings);
+                        start = i;
+                        self.state = State::Code;
+                        continue;
+                    }
+                    i += 1;
+                }
+                State::RawString(hashes) => {
+                    let is_end = rest[0] == b'"'
+                        && rest.len() > hashes
+                        && rest[1..=hashes].iter().all(|&b| b == b'#');
+                    if is_end {
+                        i += 1 + hashes;
+                        push_region(&mut regions, start..i, Scope::Strings);
+                        start = i;
+                        self.state = State::Code;
+                        continue;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        push_region(&mut regions, start..bytes.len(), self.state.scope());
+        if let State::String(index) = self.state {
+            if !self.syntax.strings[index].multiline {
+                self.state = State::Code;
+            }
+        }
+        regions
+    }
+}
+
+// If a Rust raw string such as r#"foo"# or br"foo" starts at index `i`, return
+// the length of its opening delimiter and its number of '#'
+fn raw_string_start(bytes: &[u8], i: usize) -> Option<(usize, usize)> {
+    let is_identifier_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    if i > 0 && is_identifier_byte(bytes[i - 1]) {
+        return None;
+    }
+    let rest = &bytes[i..];
+    let prefix_len = [&b"br"[..], b"cr", b"r"]
+        .iter()
+        .find(|prefix| rest.starts_with(prefix))?
+        .len();
+    let hashes = rest[prefix_len..]
+        .iter()
+        .take_while(|&&b| b == b'#')
+        .count();
+    if rest.get(prefix_len + hashes) != Some(&b'"') {
+        return None;
+    }
+    Some((prefix_len + hashes + 1, hashes))
+}
+
+// If `rest` starts with a Rust char literal such as 'a' or '\'', return its
+// length. Return None for lifetimes such as 'a
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let after_quote = rest.strip_prefix('\'')?;
+    if let Some(escaped) = after_quote.strip_prefix('\\') {
+        // Note: skip the escaped character, which may be a quote
+        let mut chars = escaped.char_indices();
+        chars.next()?;
+        let (end, _) = chars.find(|(_, c)| *c == '\'')?;
+        return Some(2 + end + 1);
+    }
+    let c = after_quote.chars().next()?;
+    if c == '\'' || !after_quote[c.len_utf8()..].starts_with('\'') {
+        return None;
+    }
+    Some(1 + c.len_utf8() + 1)
+}
+
+fn push_region(regions: &mut Vec<(Range<usize>, Scope)>, range: Range<usize>, scope: Scope) {
+    if !range.is_empty() {
+        regions.push((range, scope));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(lexer: &mut Lexer, line: &str) -> Vec<(String, Scope)> {
+        lexer
+            .split_line(line)
+            .into_iter()
+            .map(|(range, scope)| (line[range].to_string(), scope))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_rust_line() {
+        let mut lexer = Lexer::new(Language::Rust);
+        let actual = split(&mut lexer, "let foo = \"f\\\"oo\"; // foo\n");
+        assert_eq!(
+            actual,
+            vec![
+                ("let foo = ".to_string(), Scope::Code),
+                ("\"f\\\"oo\"".to_string(), Scope::Strings),
+                ("; ".to_string(), Scope::Code),
+                ("// foo\n".to_string(), Scope::Comments),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_char_literals() {
+        let mut lexer = Lexer::new(Language::Rust);
+        assert_eq!(
+            split(&mut lexer, "// é\n"),
+            vec![("// é\n".to_string(), Scope::Comments)]
+        );
+        assert_eq!(
+            split(&mut lexer, "let é = 'é';\n"),
+            vec![
+                ("let é = ".to_string(), Scope::Code),
+                ("'é'".to_string(), Scope::Strings),
+                (";\n".to_string(), Scope::Code),
+            ]
+        );
+        assert_eq!(
+            split(&mut lexer, "if c == '\"' { foo }\n"),
+            vec![
+                ("if c == ".to_string(), Scope::Code),
+                ("'\"'".to_string(), Scope::Strings),
+                (" { foo }\n".to_string(), Scope::Code),
+            ]
+        );
+        // The string above must not span several lines
+        assert_eq!(
+            split(&mut lexer, "foo(); // foo\n"),
+            vec![
+                ("foo(); ".to_string(), Scope::Code),
+                ("// foo\n".to_string(), Scope::Comments),
+            ]
+        );
+        assert_eq!(
+            split(&mut lexer, "let q = '\\''; \"foo\"\n"),
+            vec![
+                ("let q = ".to_string(), Scope::Code),
+                ("'\\''".to_string(), Scope::Strings),
+                ("; ".to_string(), Scope::Code),
+                ("\"foo\"".to_string(), Scope::Strings),
+                ("\n".to_string(), Scope::Code),
+            ]
+        );
+        // Lifetimes are code
+        assert_eq!(
+            split(&mut lexer, "fn foo<'a>(x: &'a str) -> &'a str { \"'\" }\n"),
+            vec![
+                (
+                    "fn foo<'a>(x: &'a str) -> &'a str { ".to_string(),
+                    Scope::Code
+                ),
+                ("\"'\"".to_string(), Scope::Strings),
+                (" }\n".to_string(), Scope::Code),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_raw_strings() {
+        let mut lexer = Lexer::new(Language::Rust);
+        assert_eq!(
+            split(&mut lexer, "let s = r#\"a \" b\"#; // foo\n"),
+            vec![
+                ("let s = ".to_string(), Scope::Code),
+                ("r#\"a \" b\"#".to_string(), Scope::Strings),
+                ("; ".to_string(), Scope::Code),
+                ("// foo\n".to_string(), Scope::Comments),
+            ]
+        );
+        assert_eq!(
+            split(&mut lexer, "let s = br\"\\\"; foo(b\"foo\")\n"),
+            vec![
+                ("let s = ".to_string(), Scope::Code),
+                ("br\"\\\"".to_string(), Scope::Strings),
+                ("; foo(b".to_string(), Scope::Code),
+                ("\"foo\"".to_string(), Scope::Strings),
+                (")\n".to_string(), Scope::Code),
+            ]
+        );
+        // Raw strings can span several lines
+        split(&mut lexer, "let s = r##\"foo \"#\n");
+        assert_eq!(
+            split(&mut lexer, "bar\"## + bar\n"),
+            vec![
+                ("bar\"##".to_string(), Scope::Strings),
+                (" + bar\n".to_string(), Scope::Code),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_and_multiline_strings() {
+        let mut lexer = Lexer::new(Language::Python);
+        assert_eq!(
+            split(&mut lexer, "foo = \"\"\"foo\n"),
+            vec![
+                ("foo = ".to_string(), Scope::Code),
+                ("\"\"\"foo\n".to_string(), Scope::Strings),
+            ]
+        );
+        assert_eq!(
+            split(&mut lexer, "foo\"\"\" # foo\n"),
+            vec![
+                ("foo\"\"\"".to_string(), Scope::Strings),
+                (" ".to_string(), Scope::Code),
+                ("# foo\n".to_string(), Scope::Comments),
+            ]
+        );
+
+        let mut lexer = Lexer::new(Language::C);
+        split(&mut lexer, "int foo; /* foo\n");
+        assert_eq!(
+            split(&mut lexer, "foo */ foo();\n"),
+            vec![
+                ("foo */".to_string(), Scope::Comments),
+                (" foo();\n".to_string(), Scope::Code),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let detector = LanguageDetector::new().unwrap();
+        assert_eq!(
+            detector.detect(Path::new("src/main.rs")),
+            Some(Language::Rust)
+        );
+        assert_eq!(
+            detector.detect(Path::new("app.ts")),
+            Some(Language::TypeScript)
+        );
+        assert_eq!(detector.detect(Path::new("app.tsx")), Some(Language::Tsx));
+        assert_eq!(detector.detect(Path::new("README.md")), None);
+    }
+}
@@ -1,11 +1,17 @@
+mod directives;
 mod directory_patcher;
+mod error;
 mod file_patcher;
+mod git;
+mod identifiers;
+mod lexer;
 mod query;
 mod replacer;
 mod settings;
-pub use settings::Settings;
+pub use settings::{DiffStyle, GitSelection, OutputFormat, Scope, Settings, SettingsBuilder};
 mod stats;
 pub use crate::replacer::{replace, Replacement};
-pub use directory_patcher::DirectoryPatcher;
+pub use directory_patcher::{DirectoryPatcher, DirectoryPatcherBuilder};
+pub use error::Error;
 pub use query::Query;
-pub use stats::Stats;
+pub use stats::{FileStats, FileTypeStats, Outcome, Stats};
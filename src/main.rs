@@ -1,11 +1,15 @@
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use colored::*;
 use isatty::stdout_isatty;
 use std::io::prelude::*;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 use std::process;
 use structopt::StructOpt;
 
+mod config;
+use config::Config;
+
 #[derive(Debug)]
 enum ColorWhen {
     Always,
@@ -44,36 +48,275 @@ EXAMPLES:
 
     Replace 'FooBar' with 'SpamEggs', 'foo_bar' with 'spam_eggs', ...
     $ ruplacer --subvert FooBar SpamEggs
+
+    Look for 'foo' in the src/ directory, without replacing anything
+    $ ruplacer --search foo src/
+
+    Replace 'foo' with 'bar' in the src/ and tests/ directories, and in build.rs
+    $ ruplacer foo bar src/ tests/ build.rs
+
+EXIT STATUS:
+    0   Some matches were found
+    1   An error occurred
+    2   Nothing was found (unless --allow-no-match is used)
+    3   The pattern, or one of the filter regexes, is invalid
+    4   Some files or directories could not be read
 "
 )]
 struct Options {
     #[structopt(long = "go", help = "Write the changes to the filesystem")]
     go: bool,
 
-    #[structopt(help = "The pattern to search for")]
-    pattern: String,
+    #[structopt(
+        long = "allow-no-match",
+        help = "Exit with status 0 instead of 2 when nothing is found"
+    )]
+    allow_no_match: bool,
+
+    #[structopt(
+        long = "allow-dirty",
+        help = "Write to files even if they have uncommitted changes in git, are not tracked, or are not in a git repository"
+    )]
+    allow_dirty: bool,
+
+    #[structopt(
+        help = "The pattern to search for. Not used with --search, which takes the pattern as its value",
+        required_unless = "search"
+    )]
+    pattern: Option<String>,
 
-    #[structopt(help = "The replacement")]
-    replacement: String,
+    #[structopt(help = "The replacement", required_unless = "search")]
+    replacement: Option<String>,
 
     #[structopt(
         parse(from_os_str),
-        help = "The source path. Defaults to the working directory"
+        help = "The source paths (directories or files). Defaults to the working directory"
     )]
-    path: Option<PathBuf>,
+    paths: Vec<PathBuf>,
 
     #[structopt(
         long = "--no-regex",
+        short = "F",
+        alias = "fixed-strings",
         help = "Interpret pattern as a raw string. Default is: regex"
     )]
     no_regex: bool,
 
-    #[structopt(long = "--hidden", help = "Also patch hidden files")]
+    #[structopt(
+        long = "--ignore-case",
+        short = "i",
+        overrides_with = "no_ignore_case",
+        help = "Match the pattern case insensitively"
+    )]
+    ignore_case: bool,
+
+    #[structopt(
+        long = "--no-ignore-case",
+        overrides_with = "ignore_case",
+        help = "Match the pattern case sensitively, even if the configuration says otherwise"
+    )]
+    no_ignore_case: bool,
+
+    #[structopt(
+        long = "--smart-case",
+        short = "S",
+        overrides_with = "no_smart_case",
+        help = "Match case insensitively if the pattern is all lowercase, case sensitively otherwise"
+    )]
+    smart_case: bool,
+
+    #[structopt(
+        long = "--no-smart-case",
+        overrides_with = "smart_case",
+        help = "Disable --smart-case, even if the configuration enables it"
+    )]
+    no_smart_case: bool,
+
+    #[structopt(
+        long = "--search",
+        value_name = "pattern",
+        help = "Only look for <pattern> and print the matching lines. Every positional argument is then a source path"
+    )]
+    search: Option<String>,
+
+    #[structopt(
+        long = "--count",
+        short = "c",
+        requires = "search",
+        help = "Only print the number of matches in each matching file"
+    )]
+    count: bool,
+
+    #[structopt(
+        long = "--files-with-matches",
+        short = "l",
+        requires = "search",
+        conflicts_with = "count",
+        help = "Only print the path of each matching file"
+    )]
+    files_with_matches: bool,
+
+    #[structopt(
+        long = "--files-only",
+        raw(conflicts_with_all = r#"&["search", "stats_only", "quiet"]"#),
+        help = "Only print the path of each file to patch, followed by its number of replacements"
+    )]
+    files_only: bool,
+
+    #[structopt(
+        long = "--stats-only",
+        raw(conflicts_with_all = r#"&["search", "quiet"]"#),
+        help = "Only print the statistics at the end of the run"
+    )]
+    stats_only: bool,
+
+    #[structopt(
+        long = "--stats-breakdown",
+        conflicts_with = "quiet",
+        help = "At the end of the run, print statistics for each matching file and for each file type"
+    )]
+    stats_breakdown: bool,
+
+    #[structopt(
+        long = "--quiet",
+        short = "q",
+        raw(conflicts_with_all = r#"&["count", "files_with_matches"]"#),
+        help = "Do not print anything. Use the exit code to know whether the pattern was found"
+    )]
+    quiet: bool,
+
+    #[structopt(
+        long = "--diff-style",
+        help = "How to print each replacement. Choose between 'lines', 'word', or 'side-by-side'. Default is 'lines'"
+    )]
+    diff_style: Option<ruplacer::DiffStyle>,
+
+    #[structopt(
+        long = "--max-columns",
+        short = "M",
+        value_name = "num",
+        help = "When printing lines longer than <num> characters, elide the text far from the matches"
+    )]
+    max_columns: Option<usize>,
+
+    #[structopt(
+        long = "--after-context",
+        short = "A",
+        value_name = "num",
+        help = "Print <num> lines after each matching line"
+    )]
+    after_context: Option<usize>,
+
+    #[structopt(
+        long = "--before-context",
+        short = "B",
+        value_name = "num",
+        help = "Print <num> lines before each matching line"
+    )]
+    before_context: Option<usize>,
+
+    #[structopt(
+        long = "--context",
+        short = "C",
+        value_name = "num",
+        help = "Print <num> lines before and after each matching line. Overridden by -A and -B"
+    )]
+    context: Option<usize>,
+
+    #[structopt(
+        long = "--max-replacements",
+        help = "Replace at most <max_replacements> matches in each file"
+    )]
+    max_replacements_per_file: Option<usize>,
+
+    #[structopt(
+        long = "--max-replacements-per-line",
+        help = "Replace at most <max_replacements_per_line> matches in each line"
+    )]
+    max_replacements_per_line: Option<usize>,
+
+    #[structopt(
+        long = "--occurrence",
+        help = "Only replace the Nth match in each file, starting at 1. Can be used several times",
+        parse(try_from_str = "parse_occurrence"),
+        multiple = true,
+        number_of_values = 1
+    )]
+    occurrences: Vec<usize>,
+
+    #[structopt(
+        long = "--lines",
+        help = "Only patch lines in the given range, such as 100-200, 100- or 42. Can be used several times",
+        parse(try_from_str = "parse_line_range"),
+        multiple = true,
+        number_of_values = 1
+    )]
+    line_ranges: Vec<RangeInclusive<usize>>,
+
+    #[structopt(
+        long = "--if-contains",
+        help = "Only patch files containing a match for the given regex"
+    )]
+    if_contains: Option<String>,
+
+    #[structopt(
+        long = "--unless-contains",
+        help = "Do not patch files containing a match for the given regex"
+    )]
+    unless_contains: Option<String>,
+
+    #[structopt(
+        long = "--only-lines",
+        help = "Only patch lines matching the given regex"
+    )]
+    only_lines: Option<String>,
+
+    #[structopt(
+        long = "--skip-lines",
+        help = "Do not patch lines matching the given regex"
+    )]
+    skip_lines: Option<String>,
+
+    #[structopt(
+        long = "--scope",
+        help = "Only patch 'code', 'comments', 'strings' or whole 'identifiers'. Default is 'all'. Files written in an unknown language are skipped"
+    )]
+    scope: Option<ruplacer::Scope>,
+
+    #[structopt(
+        long = "--hidden",
+        overrides_with = "no_hidden",
+        help = "Also patch hidden files"
+    )]
     hidden: bool,
 
-    #[structopt(long = "--ignored", help = "Also patch ignored files")]
+    #[structopt(
+        long = "--no-hidden",
+        overrides_with = "hidden",
+        help = "Skip hidden files, even if the configuration says otherwise"
+    )]
+    no_hidden: bool,
+
+    #[structopt(
+        long = "--ignored",
+        overrides_with = "no_ignored",
+        help = "Also patch ignored files"
+    )]
     ignored: bool,
 
+    #[structopt(
+        long = "--no-ignored",
+        overrides_with = "ignored",
+        help = "Skip ignored files, even if the configuration says otherwise"
+    )]
+    no_ignored: bool,
+
+    #[structopt(
+        long = "--no-ruplacerignore",
+        help = "Do not honor .ruplacerignore files"
+    )]
+    no_ruplacerignore: bool,
+
     #[structopt(
         long = "--word-regex",
         short = "-w",
@@ -83,6 +326,7 @@ struct Options {
 
     #[structopt(
         long = "--subvert",
+        raw(conflicts_with_all = r#"&["ignore_case", "smart_case"]"#),
         help = "Replace all variants of the pattern (snake_case, CamelCase and so on)"
     )]
     subvert: bool,
@@ -105,6 +349,91 @@ struct Options {
     )]
     ignored_file_types: Vec<String>,
 
+    #[structopt(
+        long = "--no-config",
+        help = "Ignore the .ruplacer.toml files and the user configuration file"
+    )]
+    no_config: bool,
+
+    #[structopt(
+        long = "include",
+        value_name = "glob",
+        help = "Only patch paths matching <glob>, relative to the source path. Takes precedence over ignore rules",
+        multiple = true,
+        number_of_values = 1
+    )]
+    included_globs: Vec<String>,
+
+    #[structopt(
+        long = "exclude",
+        value_name = "glob",
+        help = "Never patch paths matching <glob>, relative to the source path",
+        multiple = true,
+        number_of_values = 1
+    )]
+    excluded_globs: Vec<String>,
+
+    #[structopt(
+        long = "files-from",
+        value_name = "path",
+        parse(from_os_str),
+        help = "Only patch the files listed in <path>, separated by newlines or NUL characters. Use '-' to read the list from stdin"
+    )]
+    files_from: Option<PathBuf>,
+
+    #[structopt(
+        long = "filter-listed-files",
+        help = "Apply the --type, --type-not, --include, --exclude, --max-filesize and hidden files filters to the files given with --files-from",
+        requires = "files_from"
+    )]
+    filter_listed_files: bool,
+
+    #[structopt(
+        long = "git-tracked",
+        help = "Only patch files tracked by git",
+        raw(conflicts_with_all = r#"&["changed_since", "staged"]"#)
+    )]
+    git_tracked: bool,
+
+    #[structopt(
+        long = "changed-since",
+        value_name = "revision",
+        help = "Only patch files modified relative to <revision> in git, including uncommitted changes",
+        conflicts_with = "staged"
+    )]
+    changed_since: Option<String>,
+
+    #[structopt(long = "staged", help = "Only patch files with changes staged in git")]
+    staged: bool,
+
+    #[structopt(
+        long = "max-depth",
+        value_name = "num",
+        help = "Do not walk directories more than <num> levels below the source paths"
+    )]
+    max_depth: Option<usize>,
+
+    #[structopt(
+        long = "follow",
+        short = "L",
+        help = "Follow symbolic links. Loops are reported and skipped"
+    )]
+    follow_links: bool,
+
+    #[structopt(
+        long = "one-file-system",
+        help = "Do not walk into directories on other file systems than the source paths"
+    )]
+    one_file_system: bool,
+
+    #[structopt(
+        long = "max-filesize",
+        value_name = "size",
+        help = "Skip files larger than <size>, in bytes. Accepts the K, M and G suffixes, such as 50K or 2M",
+        parse(try_from_str = "parse_filesize")
+    )]
+    max_filesize: Option<u64>,
+
     #[structopt(long = "type-list", help = "List the known file types")]
     file_type_list: bool,
 
@@ -115,19 +444,160 @@ struct Options {
     color_when: Option<ColorWhen>,
 }
 
-fn regex_query_or_die(pattern: &str, replacement: &str, word: bool) -> ruplacer::Query {
+fn parse_filesize(s: &str) -> Result<u64, Error> {
+    let (number, multiplier) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a valid size", s))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("'{}' is too large", s))
+}
+
+// Return the value of a flag that can be negated with --no-<flag>,
+// or None if neither was given
+fn negatable_flag(flag: bool, no_flag: bool) -> Option<bool> {
+    if flag {
+        Some(true)
+    } else if no_flag {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn parse_occurrence(s: &str) -> Result<usize, Error> {
+    let occurrence: usize = s
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a valid occurrence", s))?;
+    if occurrence == 0 {
+        return Err(anyhow!("occurrences start at 1"));
+    }
+    Ok(occurrence)
+}
+
+fn parse_line_range(s: &str) -> Result<RangeInclusive<usize>, Error> {
+    let parse_lineno = |x: &str| -> Result<usize, Error> {
+        let lineno: usize = x
+            .parse()
+            .map_err(|_| anyhow!("'{}' is not a valid line number", x))?;
+        if lineno == 0 {
+            return Err(anyhow!("line numbers start at 1"));
+        }
+        Ok(lineno)
+    };
+    match s.find('-') {
+        None => {
+            let lineno = parse_lineno(s)?;
+            Ok(lineno..=lineno)
+        }
+        Some(index) => {
+            let (start, end) = (&s[..index], &s[index + 1..]);
+            let start = if start.is_empty() {
+                1
+            } else {
+                parse_lineno(start)?
+            };
+            let end = if end.is_empty() {
+                usize::MAX
+            } else {
+                parse_lineno(end)?
+            };
+            if start > end {
+                return Err(anyhow!("'{}' is not a valid range: {} > {}", s, start, end));
+            }
+            Ok(start..=end)
+        }
+    }
+}
+
+fn regex_query(
+    pattern: &str,
+    replacement: &str,
+    word: bool,
+    ignore_case: bool,
+) -> Result<ruplacer::Query> {
     let actual_pattern = if word {
         format!(r"\b({})\b", pattern)
     } else {
         pattern.to_string()
     };
-    let re = regex::Regex::new(&actual_pattern);
-    if let Err(e) = re {
-        eprintln!("{}: {}", "Invalid regex".bold().red(), e);
-        process::exit(1);
+    let mut builder = regex::RegexBuilder::new(&actual_pattern);
+    builder.case_insensitive(ignore_case);
+    let re = build_regex(&builder)?;
+    Ok(ruplacer::Query::regex(re, replacement))
+}
+
+// Used to exit with ExitStatus::InvalidPattern
+#[derive(Debug)]
+struct InvalidPattern(regex::Error);
+
+impl std::fmt::Display for InvalidPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPattern {}
+
+fn build_regex(builder: &regex::RegexBuilder) -> Result<regex::Regex> {
+    builder.build().map_err(|e| InvalidPattern(e).into())
+}
+
+fn substring_query(pattern: &str, replacement: &str, ignore_case: bool) -> ruplacer::Query {
+    if !ignore_case {
+        return ruplacer::Query::substring(pattern, replacement);
+    }
+    // Matching a raw string case insensitively is best left to the regex
+    // engine, so escape the pattern and make sure '$' in the replacement
+    // is not treated as a capture group reference
+    let re = regex::RegexBuilder::new(&regex::escape(pattern))
+        .case_insensitive(true)
+        .build()
+        .expect("escaped pattern should always be a valid regex");
+    ruplacer::Query::regex(re, &replacement.replace('$', "$$"))
+}
+
+// Like ripgrep, --smart-case only ignores case when the pattern does not
+// contain any upper case literal. In regexes, escape sequences such as \S or
+// \p{Lu}, flags such as (?U) and group names are not literals
+fn is_smart_case_insensitive(pattern: &str, is_regex: bool) -> bool {
+    if !is_regex {
+        return !pattern.chars().any(char::is_uppercase);
     }
-    let re = re.unwrap();
-    ruplacer::Query::regex(re, replacement)
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some('p') | Some('P') = chars.next() {
+                    // Either \pL or \p{Lu}
+                    if chars.next() == Some('{') {
+                        chars.by_ref().find(|&c| c == '}');
+                    }
+                }
+            }
+            '(' if chars.peek() == Some(&'?') => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        ':' | ')' => break,
+                        '<' => {
+                            chars.by_ref().find(|&c| c == '>');
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            c if c.is_uppercase() => return false,
+            _ => (),
+        }
+    }
+    true
 }
 
 // Set proper env variable so that the colored crate behaves properly.
@@ -155,6 +625,44 @@ fn print_stats(stats: &ruplacer::Stats, dry_run: bool) {
     println!("{}", stats)
 }
 
+// Print the warnings and errors collected while patching the files
+fn print_diagnostics(stats: &ruplacer::Stats) {
+    for warning in stats.warnings() {
+        eprintln!("Warning: {}", format_chain(warning));
+    }
+    for error in stats.errors() {
+        eprintln!("Error: {}", format_chain(error));
+    }
+}
+
+// Mention the command line flags instead of the settings of the library
+fn cli_error(error: ruplacer::Error) -> Error {
+    match error {
+        ruplacer::Error::DirtyFiles { paths } => {
+            let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+            anyhow!(
+                "Some files have uncommitted changes: {}. Commit or stash them first, or use --allow-dirty",
+                paths.join(", ")
+            )
+        }
+        ruplacer::Error::UncheckedFiles { source } => Error::new(*source).context(
+            "Could not check for uncommitted changes. Make sure the files are in a git repository, or use --allow-dirty",
+        ),
+        error => error.into(),
+    }
+}
+
+// Like anyhow's "{:#}", which cannot be used on a borrowed error
+fn format_chain(error: &dyn std::error::Error) -> String {
+    let mut res = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        res.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    res
+}
+
 fn on_type_list() {
     println!("Known file types:");
     let mut types_builder = ignore::types::TypesBuilder::new();
@@ -166,91 +674,442 @@ fn on_type_list() {
     }
 }
 
-fn main() -> Result<()> {
+/// Documented in the EXIT STATUS section of the help
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+    Found = 0,
+    Error = 1,
+    NothingFound = 2,
+    InvalidPattern = 3,
+    PartialErrors = 4,
+}
+
+fn main() -> process::ExitCode {
+    let status = match run() {
+        Ok(status) => status,
+        Err(err) => match err.downcast_ref::<InvalidPattern>() {
+            Some(e) => {
+                eprintln!("{}: {}", "Invalid regex".bold().red(), e);
+                ExitStatus::InvalidPattern
+            }
+            None => {
+                eprintln!("Error: {:?}", err);
+                ExitStatus::Error
+            }
+        },
+    };
+    process::ExitCode::from(status as u8)
+}
+
+fn run() -> Result<ExitStatus> {
     let opt = Options::from_args();
     let Options {
+        after_context,
+        allow_dirty,
+        allow_no_match,
+        before_context,
+        changed_since,
         color_when,
+        context,
+        count,
+        diff_style,
+        excluded_globs,
         file_type_list,
+        files_from,
+        files_only,
+        files_with_matches,
+        filter_listed_files,
+        follow_links,
+        git_tracked,
         go,
         hidden,
+        if_contains,
+        ignore_case,
         ignored,
         ignored_file_types,
+        included_globs,
+        line_ranges,
+        max_columns,
+        max_depth,
+        max_filesize,
+        max_replacements_per_file,
+        max_replacements_per_line,
+        no_config,
+        no_hidden,
+        no_ignore_case,
+        no_ignored,
         no_regex,
-        path,
+        no_ruplacerignore,
+        no_smart_case,
+        occurrences,
+        one_file_system,
+        only_lines,
+        paths,
         pattern,
+        quiet,
         replacement,
+        scope,
+        search,
         selected_file_types,
+        skip_lines,
+        smart_case,
+        staged,
+        stats_breakdown,
+        stats_only,
         subvert,
+        unless_contains,
         word_regex,
     } = opt;
 
     if file_type_list {
         on_type_list();
-        return Ok(());
+        return Ok(ExitStatus::Found);
     }
 
     let dry_run = !go;
 
-    let color_when = &color_when.unwrap_or(ColorWhen::Auto);
+    // Like with ripgrep's -e option, --search gives the pattern, so
+    // there is no positional pattern nor replacement, only source paths
+    let search_pattern = search;
+    let search = search_pattern.is_some();
+    let (pattern, replacement, mut paths) = match search_pattern {
+        Some(search_pattern) => {
+            let mut all_paths: Vec<_> = pattern
+                .into_iter()
+                .chain(replacement)
+                .map(PathBuf::from)
+                .collect();
+            all_paths.extend(paths);
+            (search_pattern, String::new(), all_paths)
+        }
+        None => (
+            pattern.unwrap_or_default(),
+            replacement.unwrap_or_default(),
+            paths,
+        ),
+    };
+    let listed_files = files_from.is_some();
+    if let Some(files_from) = files_from {
+        if !paths.is_empty() {
+            return Err(anyhow!(
+                "--files-from cannot be used along with source paths"
+            ));
+        }
+        paths = read_file_list(&files_from)?;
+    } else if paths.is_empty() {
+        paths.push(Path::new(".").to_path_buf());
+    }
+    let read_stdin = !listed_files && paths.iter().any(|p| p == Path::new("-"));
+    if read_stdin && paths.len() > 1 {
+        return Err(anyhow!("'-' cannot be used along with other source paths"));
+    }
+
+    // Options given on the command line take precedence over the ones
+    // from the configuration files. Note: configuration is looked up
+    // from the first source path only
+    let config = if no_config {
+        Config::default()
+    } else if read_stdin || listed_files {
+        Config::load(Path::new("."))?
+    } else {
+        Config::load(&paths[0])?
+    };
+    let cli_config = Config {
+        hidden: negatable_flag(hidden, no_hidden),
+        ignored: negatable_flag(ignored, no_ignored),
+        ignore_case: negatable_flag(ignore_case, no_ignore_case),
+        smart_case: negatable_flag(smart_case, no_smart_case),
+        ..Default::default()
+    };
+    let config = config.merge(cli_config);
+    let hidden = config.hidden.unwrap_or(false);
+    let ignored = config.ignored.unwrap_or(false);
+    let ignore_case = config.ignore_case.unwrap_or(false);
+    let smart_case = config.smart_case.unwrap_or(false);
+    let color_when = match (color_when, config.color) {
+        (Some(color_when), _) => color_when,
+        (None, Some(color)) => color
+            .parse()
+            .context("Invalid 'color' value in configuration file")?,
+        (None, None) => ColorWhen::Auto,
+    };
+    let scope = match (scope, config.scope) {
+        (Some(scope), _) => scope,
+        (None, Some(scope)) => scope
+            .parse()
+            .context("Invalid 'scope' value in configuration file")?,
+        (None, None) => ruplacer::Scope::default(),
+    };
+    let selected_file_types = if selected_file_types.is_empty() {
+        config.selected_file_types.unwrap_or_default()
+    } else {
+        selected_file_types
+    };
+    let ignored_file_types = if ignored_file_types.is_empty() {
+        config.ignored_file_types.unwrap_or_default()
+    } else {
+        ignored_file_types
+    };
+    let included_globs = if included_globs.is_empty() {
+        config.included_globs.unwrap_or_default()
+    } else {
+        included_globs
+    };
+    let excluded_globs = if excluded_globs.is_empty() {
+        config.excluded_globs.unwrap_or_default()
+    } else {
+        excluded_globs
+    };
+
     configure_color(&color_when);
 
+    let ignore_case = ignore_case || (smart_case && is_smart_case_insensitive(&pattern, !no_regex));
+
     let query = if no_regex {
-        ruplacer::Query::substring(&pattern, &replacement)
+        substring_query(&pattern, &replacement, ignore_case)
     } else if subvert {
         ruplacer::Query::subvert(&pattern, &replacement)
     } else {
-        regex_query_or_die(&pattern, &replacement, word_regex)
+        regex_query(&pattern, &replacement, word_regex, ignore_case)?
     };
 
-    let settings = ruplacer::Settings {
-        dry_run,
-        hidden,
-        ignored,
-        selected_file_types,
-        ignored_file_types,
-    };
+    let filter_regex = |pattern: &str| build_regex(&regex::RegexBuilder::new(pattern));
+    let if_contains = if_contains.as_deref().map(filter_regex).transpose()?;
+    let unless_contains = unless_contains.as_deref().map(filter_regex).transpose()?;
+    let only_lines = only_lines.as_deref().map(filter_regex).transpose()?;
+    let skip_lines = skip_lines.as_deref().map(filter_regex).transpose()?;
 
-    let path = path.unwrap_or_else(|| Path::new(".").to_path_buf());
-    if path == PathBuf::from("-") {
-        run_on_stdin(query)
+    let output_format = if count {
+        ruplacer::OutputFormat::Count
+    } else if files_only {
+        ruplacer::OutputFormat::FilesOnly
+    } else if files_with_matches {
+        ruplacer::OutputFormat::FilesWithMatches
+    } else if stats_only {
+        ruplacer::OutputFormat::StatsOnly
+    } else if quiet {
+        ruplacer::OutputFormat::Quiet
     } else {
-        run_on_directory(path, settings, query)
+        ruplacer::OutputFormat::Lines
+    };
+    // Note: there is no file to list, or to compute statistics for, on stdin
+    if read_stdin {
+        let file_options = [
+            ("--files-with-matches", files_with_matches),
+            ("--files-only", files_only),
+            ("--stats-only", stats_only),
+            ("--stats-breakdown", stats_breakdown),
+        ];
+        if let Some((option, _)) = file_options.iter().find(|(_, used)| *used) {
+            return Err(anyhow!("{} cannot be used when reading from stdin", option));
+        }
     }
+
+    let git_selection = if git_tracked {
+        Some(ruplacer::GitSelection::Tracked)
+    } else if staged {
+        Some(ruplacer::GitSelection::Staged)
+    } else {
+        changed_since.map(ruplacer::GitSelection::ChangedSince)
+    };
+
+    let settings = ruplacer::Settings::builder()
+        .dry_run(dry_run)
+        .hidden(hidden)
+        .ignored(ignored)
+        .no_ruplacerignore(no_ruplacerignore)
+        .filter_listed_files(filter_listed_files)
+        .types(selected_file_types)
+        .types_not(ignored_file_types)
+        .include(included_globs)
+        .exclude(excluded_globs)
+        .max_depth(max_depth)
+        .follow_links(follow_links)
+        .one_file_system(one_file_system)
+        .max_filesize(max_filesize)
+        .git_selection(git_selection)
+        .allow_dirty(allow_dirty)
+        .search_only(search)
+        .output_format(output_format)
+        .diff_style(diff_style.unwrap_or_default())
+        .max_columns(max_columns)
+        .context_before(before_context.or(context).unwrap_or(0))
+        .context_after(after_context.or(context).unwrap_or(0))
+        .max_replacements_per_file(max_replacements_per_file)
+        .max_replacements_per_line(max_replacements_per_line)
+        .occurrences(occurrences)
+        .line_ranges(line_ranges)
+        .if_contains(if_contains)
+        .unless_contains(unless_contains)
+        .only_lines(only_lines)
+        .skip_lines(skip_lines)
+        .scope(scope)
+        .build();
+
+    let quiet = settings.output_format == ruplacer::OutputFormat::Quiet;
+    let outcome = if read_stdin {
+        run_on_stdin(query, search, settings.output_format)?
+    } else {
+        run_on_paths(paths, listed_files, stats_breakdown, settings, query)?
+    };
+    Ok(match outcome {
+        ruplacer::Outcome::Found => ExitStatus::Found,
+        ruplacer::Outcome::NothingFound if allow_no_match => ExitStatus::Found,
+        ruplacer::Outcome::NothingFound => {
+            if !quiet {
+                #[allow(clippy::print_literal)]
+                {
+                    let message = if search {
+                        "nothing found"
+                    } else {
+                        "nothing found to replace"
+                    };
+                    eprintln!("{}: {}", "Error".bold().red(), message);
+                }
+            }
+            ExitStatus::NothingFound
+        }
+        ruplacer::Outcome::PartialErrors => ExitStatus::PartialErrors,
+    })
+}
+
+// Read a list of files separated by NUL characters (as printed by `git grep -lz`
+// or `fd -0`), or by newlines otherwise
+fn read_file_list(source: &Path) -> Result<Vec<PathBuf>> {
+    let contents = if source == Path::new("-") {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .context("Could not read the list of files from stdin")?;
+        contents
+    } else {
+        std::fs::read_to_string(source).with_context(|| {
+            format!("Could not read the list of files from {}", source.display())
+        })?
+    };
+    let separator = if contents.contains('\0') { '\0' } else { '\n' };
+    Ok(contents
+        .split(separator)
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
 }
 
-fn run_on_stdin(query: ruplacer::Query) -> Result<()> {
+// With --count, only print the total number of matches, and with --quiet,
+// print nothing
+fn run_on_stdin(
+    query: ruplacer::Query,
+    search: bool,
+    output_format: ruplacer::OutputFormat,
+) -> Result<ruplacer::Outcome> {
+    let count = output_format == ruplacer::OutputFormat::Count;
+    let quiet = output_format == ruplacer::OutputFormat::Quiet;
     let stdin = std::io::stdin();
+    let mut num_matches = 0;
     for line in stdin.lock().lines() {
         let line = line?;
         let replacement = ruplacer::replace(&line, &query);
-        if let Some(replacement) = replacement {
-            println!("{}", replacement.output());
-        } else {
-            println!("{}", line);
+        if let Some(replacement) = &replacement {
+            num_matches += replacement.num_fragments();
         }
+        match replacement {
+            _ if count || quiet => (),
+            Some(replacement) if search => replacement.print_match(""),
+            Some(replacement) => println!("{}", replacement.output()),
+            None if search => (),
+            None => println!("{}", line),
+        }
+    }
+    if count {
+        println!("{}", num_matches);
     }
-    Ok(())
+    Ok(if num_matches == 0 {
+        ruplacer::Outcome::NothingFound
+    } else {
+        ruplacer::Outcome::Found
+    })
 }
 
-fn run_on_directory(
-    path: PathBuf,
+fn run_on_paths(
+    paths: Vec<PathBuf>,
+    listed_files: bool,
+    stats_breakdown: bool,
     settings: ruplacer::Settings,
     query: ruplacer::Query,
-) -> Result<()> {
+) -> Result<ruplacer::Outcome> {
     let dry_run = settings.dry_run;
-    let mut directory_patcher = ruplacer::DirectoryPatcher::new(&path, &settings);
-    directory_patcher.run(&query)?;
+    let search_only = settings.search_only;
+    let output_format = settings.output_format;
+    let mut directory_patcher = ruplacer::DirectoryPatcher::builder(&settings)
+        .paths(&paths)
+        .listed_files(listed_files)
+        .build();
+    let result = directory_patcher.run(&query);
     let stats = directory_patcher.stats();
-    if stats.total_replacements() == 0 {
-        #[allow(clippy::print_literal)]
-        {
-            eprintln!("{}: {}", "Error".bold().red(), "nothing found to replace");
-        }
-        process::exit(2);
+    print_diagnostics(&stats);
+    let outcome = result.map_err(cli_error)?;
+    let quiet = output_format == ruplacer::OutputFormat::Quiet;
+    if quiet {
+        return Ok(outcome);
+    }
+    if outcome == ruplacer::Outcome::NothingFound {
+        return Ok(outcome);
+    }
+    if stats_breakdown {
+        println!("{}", stats.breakdown());
+    }
+    if search_only {
+        return Ok(outcome);
     }
     print_stats(&stats, dry_run);
     if dry_run {
         println!("Re-run ruplacer with --go to write these changes to the filesystem");
     }
-    Ok(())
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smart_case() {
+        assert!(is_smart_case_insensitive("foo_bar", true));
+        assert!(!is_smart_case_insensitive("FooBar", true));
+        assert!(is_smart_case_insensitive(r"foo\S+\W\D\B", true));
+        assert!(is_smart_case_insensitive(r"\p{Lu}foo\PL", true));
+        assert!(is_smart_case_insensitive(
+            r"(?U)(?P<Name>foo)(?<Other>bar)",
+            true
+        ));
+        assert!(!is_smart_case_insensitive(r"\p{Lu}Foo", true));
+        assert!(!is_smart_case_insensitive(r"[A-Z]", true));
+        assert!(!is_smart_case_insensitive(r"foo\S", false));
+    }
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("42").unwrap(), 42..=42);
+        assert_eq!(parse_line_range("5-10").unwrap(), 5..=10);
+        assert_eq!(parse_line_range("5-5").unwrap(), 5..=5);
+        assert_eq!(parse_line_range("100-").unwrap(), 100..=usize::MAX);
+        assert_eq!(parse_line_range("-20").unwrap(), 1..=20);
+
+        assert!(parse_line_range("0").is_err());
+        assert!(parse_line_range("0-3").is_err());
+        assert!(parse_line_range("foo").is_err());
+        let err = parse_line_range("10-5").unwrap_err();
+        assert_eq!(err.to_string(), "'10-5' is not a valid range: 10 > 5");
+    }
+
+    #[test]
+    fn test_parse_occurrence() {
+        assert_eq!(parse_occurrence("1").unwrap(), 1);
+        assert_eq!(parse_occurrence("12").unwrap(), 12);
+        assert!(parse_occurrence("-1").is_err());
+        assert!(parse_occurrence("foo").is_err());
+        let err = parse_occurrence("0").unwrap_err();
+        assert_eq!(err.to_string(), "occurrences start at 1");
+    }
 }
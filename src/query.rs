@@ -9,12 +9,14 @@ use inflector::cases::traincase::*;
 pub enum Query {
     /// Substitute `old` with `new`
     Substring(String, String),
-    /// Replace the parts matching the regex with `replacement`
+    /// Replace the parts matching the regex with `replacement`.
+    /// Note: with the `serde` feature, only the pattern of the regex is
+    /// serialized, so the flags set with a `RegexBuilder` are dropped
     Regex(regex::Regex, String),
     /// Replace all instances of `pattern` with `replacement`, by
     /// using case conversion methods.
     /// This allows replacing FooBar with SpamEggs and foo_bar with spam_eggs
-    /// using only one query
+    /// using only one query.
     Subvert(Vec<(String, String)>),
 }
 
@@ -50,3 +52,218 @@ impl Query {
         Self::Subvert(items)
     }
 }
+
+// Serialized form of a Query. Regexes are stored as their pattern, so flags
+// set with a RegexBuilder are lost: use inline flags such as `(?i)` instead.
+// Subvert queries are stored as a pattern and a replacement from which
+// Query::subvert() rebuilds the same items, or as the items themselves when
+// there are none, for instance with acronyms such as `HTTPServer`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+enum SerializedQuery {
+    Substring {
+        pattern: String,
+        replacement: String,
+    },
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+    Subvert {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replacement: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        items: Vec<(String, String)>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl SerializedQuery {
+    // Look for a pattern and a replacement rebuilding the same items,
+    // starting with the snake_case ones. Note: the case conversions do not
+    // always round-trip, so keep the items when none matches
+    fn subvert(items: &[(String, String)]) -> Self {
+        let snake_case = items
+            .first()
+            .map(|(pattern, replacement)| (to_snake_case(pattern), to_snake_case(replacement)));
+        let candidates = snake_case.into_iter().chain(items.iter().cloned());
+        for (pattern, replacement) in candidates {
+            match Query::subvert(&pattern, &replacement) {
+                Query::Subvert(rebuilt) if rebuilt == items => {
+                    return SerializedQuery::Subvert {
+                        pattern: Some(pattern),
+                        replacement: Some(replacement),
+                        items: vec![],
+                    }
+                }
+                _ => (),
+            }
+        }
+        SerializedQuery::Subvert {
+            pattern: None,
+            replacement: None,
+            items: items.to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Query {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let serialized = match self {
+            Query::Substring(pattern, replacement) => SerializedQuery::Substring {
+                pattern: pattern.clone(),
+                replacement: replacement.clone(),
+            },
+            Query::Regex(regex, replacement) => SerializedQuery::Regex {
+                pattern: regex.as_str().to_string(),
+                replacement: replacement.clone(),
+            },
+            Query::Subvert(items) => SerializedQuery::subvert(items),
+        };
+        serde::Serialize::serialize(&serialized, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Query {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(
+            match <SerializedQuery as serde::Deserialize>::deserialize(deserializer)? {
+                SerializedQuery::Substring {
+                    pattern,
+                    replacement,
+                } => Query::Substring(pattern, replacement),
+                SerializedQuery::Regex {
+                    pattern,
+                    replacement,
+                } => {
+                    let regex = regex::Regex::new(&pattern).map_err(serde::de::Error::custom)?;
+                    Query::Regex(regex, replacement)
+                }
+                SerializedQuery::Subvert {
+                    pattern: Some(pattern),
+                    replacement: Some(replacement),
+                    items,
+                } if items.is_empty() => Query::subvert(&pattern, &replacement),
+                SerializedQuery::Subvert {
+                    pattern: None,
+                    replacement: None,
+                    items,
+                } if !items.is_empty() => Query::Subvert(items),
+                SerializedQuery::Subvert { .. } => {
+                    return Err(serde::de::Error::custom(
+                        "a subvert query needs either a pattern and a replacement, or items",
+                    ))
+                }
+            },
+        )
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    fn round_trip(query: &Query) -> Query {
+        let serialized = toml::to_string(query).unwrap();
+        toml::from_str(&serialized).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_query() {
+        let query = Query::regex(regex::Regex::new(r"(\w+), (\w+)").unwrap(), "$2 $1");
+        let serialized = toml::to_string(&query).unwrap();
+        assert_eq!(
+            serialized,
+            "kind = \"regex\"\npattern = '(\\w+), (\\w+)'\nreplacement = \"$2 $1\"\n"
+        );
+        match round_trip(&query) {
+            Query::Regex(regex, replacement) => {
+                assert_eq!(regex.as_str(), r"(\w+), (\w+)");
+                assert_eq!(replacement, "$2 $1");
+            }
+            _ => panic!("expected a regex query"),
+        }
+
+        let query = Query::subvert("FooBar", "SpamEggs");
+        let serialized = toml::to_string(&query).unwrap();
+        assert_eq!(
+            serialized,
+            "kind = \"subvert\"\npattern = \"foo_bar\"\nreplacement = \"spam_eggs\"\n"
+        );
+        assert_subvert_round_trip("FooBar", "SpamEggs");
+    }
+
+    fn items(query: Query) -> Vec<(String, String)> {
+        match query {
+            Query::Subvert(items) => items,
+            _ => panic!("expected a subvert query"),
+        }
+    }
+
+    fn assert_subvert_round_trip(pattern: &str, replacement: &str) {
+        let query = Query::subvert(pattern, replacement);
+        let json = serde_json::to_string(&query).unwrap();
+        let from_json: Query = serde_json::from_str(&json).unwrap();
+        assert_eq!(items(round_trip(&query)), items(from_json));
+        assert_eq!(items(round_trip(&query)), items(query));
+    }
+
+    #[test]
+    fn test_serialize_subvert_queries() {
+        // The case conversions of acronyms, digits and upper case words do
+        // not round-trip, so the items are stored instead
+        assert_subvert_round_trip("HTTPServer", "WebServer");
+        assert_subvert_round_trip("foo2bar", "spam");
+        assert_subvert_round_trip("fooBAR", "spam_eggs");
+        let serialized = toml::to_string(&Query::subvert("HTTPServer", "WebServer")).unwrap();
+        assert!(serialized.contains("items = "), "{}", serialized);
+
+        // Items not built by Query::subvert() are kept as well
+        let query = Query::Subvert(vec![("foo".to_string(), "bar".to_string())]);
+        assert_eq!(items(round_trip(&query)), items(query));
+    }
+
+    #[test]
+    fn test_deserialize_incomplete_subvert_query() {
+        let err = toml::from_str::<Query>("kind = \"subvert\"\npattern = \"foo\"\n")
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("either a pattern and a replacement"));
+    }
+
+    #[test]
+    fn test_regex_flags_are_dropped() {
+        let regex = regex::RegexBuilder::new("old")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let query = Query::regex(regex, "new");
+        match round_trip(&query) {
+            Query::Regex(regex, _) => assert!(!regex.is_match("OLD")),
+            _ => panic!("expected a regex query"),
+        }
+
+        // Inline flags are part of the pattern, so they are kept
+        let query = Query::regex(regex::Regex::new("(?i)old").unwrap(), "new");
+        match round_trip(&query) {
+            Query::Regex(regex, _) => assert!(regex.is_match("OLD")),
+            _ => panic!("expected a regex query"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_invalid_regex() {
+        let err =
+            toml::from_str::<Query>("kind = \"regex\"\npattern = \"(\"\nreplacement = \"\"\n")
+                .err()
+                .unwrap();
+        assert!(err.to_string().contains("unclosed group"));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::query::Query;
 use colored::*;
 use regex::Regex;
+use std::borrow::Cow;
+use std::ops::Range;
 
 /// Execute a query on a line of input.
 /// If there was a match, return a Replacement
@@ -31,6 +33,7 @@ pub fn replace<'a>(input: &'a str, query: &Query) -> Option<Replacement<'a>> {
         fragments,
         input,
         output,
+        max_columns: None,
     })
 }
 
@@ -40,6 +43,7 @@ pub struct Replacement<'a> {
     fragments: Fragments,
     input: &'a str,
     output: String,
+    max_columns: Option<usize>,
 }
 
 impl<'a> Replacement<'a> {
@@ -53,64 +57,351 @@ impl<'a> Replacement<'a> {
         &self.output
     }
 
-    pub(crate) fn num_fragments(&self) -> usize {
+    /// When printing lines longer than `max_columns` characters, elide
+    /// the text far from the fragments, so that huge lines, such as the ones
+    /// found in minified files, do not flood the terminal.
+    /// Fragments themselves are always printed in full.
+    pub fn set_max_columns(&mut self, max_columns: Option<usize>) {
+        self.max_columns = max_columns;
+    }
+
+    /// Return the number of matches in the input string
+    pub fn num_fragments(&self) -> usize {
         self.fragments.len()
     }
 
+    /// Only keep the fragments for which `keep` returns true, given their
+    /// byte range in the input string. `keep` is called on each fragment, in order.
+    /// Return None if there are no fragments left
+    pub(crate) fn retain_fragments<F>(self, mut keep: F) -> Option<Replacement<'a>>
+    where
+        F: FnMut(Range<usize>) -> bool,
+    {
+        // Output indexes of the kept fragments must be shifted to
+        // account for the fragments that were dropped before them
+        let mut fragments = Fragments::new();
+        let mut added = 0;
+        let mut removed = 0;
+        for (input_fragment, output_fragment) in self.fragments.into_iter() {
+            let input_range =
+                input_fragment.index..input_fragment.index + input_fragment.text.len();
+            if !keep(input_range) {
+                continue;
+            }
+            let output_index = input_fragment.index + added - removed;
+            fragments.add(
+                (input_fragment.index, &input_fragment.text),
+                (output_index, &output_fragment.text),
+            );
+            added += output_fragment.text.len();
+            removed += input_fragment.text.len();
+        }
+        if fragments.is_empty() {
+            return None;
+        }
+        let output = get_output(self.input, &fragments);
+        Some(Replacement {
+            fragments,
+            input: self.input,
+            output,
+            max_columns: self.max_columns,
+        })
+    }
+
     /// Print the replacement as two lines (red then green)
     /// ```
     /// use ruplacer::{Query, replace};
     /// let input = "let foo_bar = FooBar::new();";
     /// let query = Query::subvert("foo_bar", "spam_eggs");
     /// let replacement = replace(input, &query).unwrap();
-    /// replacement.print_self("foo.rs:3");
+    /// replacement.print_self("foo.rs:3 ");
     /// // outputs:
-    /// // foo.rs:3 let foo_bar = FooBar::new()
-    /// // foo.rs:3 let spam_eggs = SpamEggs::new()
+    /// // foo.rs:3 - let foo_bar = FooBar::new();
+    /// // foo.rs:3 + let spam_eggs = SpamEggs::new();
     /// ```
     pub fn print_self(&self, prefix: &str) {
-        let red_underline = { |x: &str| x.red().underline() };
-        let input_fragments = self.fragments.into_iter().map(|x| &x.0);
         let red_prefix = format!("{}{}", prefix, "- ".red());
-        Self::print_fragments(&red_prefix, red_underline, self.input, input_fragments);
+        print_spans(&red_prefix, self.prepare_spans(self.input_spans()));
 
-        let green_underline = { |x: &str| x.green().underline() };
         let green_prefix = format!("{}{}", prefix, "+ ".green());
-        let output_fragments = self.fragments.into_iter().map(|x| &x.1);
-        Self::print_fragments(
-            &green_prefix,
-            green_underline,
-            &self.output,
-            output_fragments,
-        );
+        print_spans(&green_prefix, self.prepare_spans(self.output_spans()));
     }
 
-    fn print_fragments<'f, C>(
-        prefix: &str,
-        color: C,
-        line: &str,
-        fragments: impl Iterator<Item = &'f Fragment>,
-    ) where
-        C: Fn(&str) -> ColoredString,
-    {
+    /// Print the replacement as a single line, like `git diff --word-diff`
+    /// ```
+    /// use ruplacer::{Query, replace};
+    /// let input = "let foo_bar = FooBar::new();";
+    /// let query = Query::subvert("foo_bar", "spam_eggs");
+    /// let replacement = replace(input, &query).unwrap();
+    /// replacement.print_word_diff("foo.rs:3 ");
+    /// // outputs:
+    /// // foo.rs:3 let [-foo_bar-]{+spam_eggs+} = [-FooBar-]{+SpamEggs+}::new();
+    /// ```
+    pub fn print_word_diff(&self, prefix: &str) {
+        print_spans(prefix, self.prepare_spans(self.word_diff_spans()));
+    }
+
+    /// Print the input line and the output line next to each other,
+    /// truncating them so that the whole line fits in `width` characters
+    /// ```
+    /// use ruplacer::{Query, replace};
+    /// let input = "let foo_bar = FooBar::new();";
+    /// let query = Query::subvert("foo_bar", "spam_eggs");
+    /// let replacement = replace(input, &query).unwrap();
+    /// replacement.print_side_by_side("foo.rs:3 ", 80);
+    /// // outputs:
+    /// // foo.rs:3 let foo_bar = FooBar::new();      | let spam_eggs = SpamEggs::new();
+    /// ```
+    pub fn print_side_by_side(&self, prefix: &str, width: usize) {
+        let column_width = (width.saturating_sub(prefix.chars().count() + 3) / 2).max(10);
+        let left = fit_spans(self.prepare_spans(self.input_spans()), column_width);
+        let right = fit_spans(self.prepare_spans(self.output_spans()), column_width);
+        let padding = column_width - spans_width(&left);
         print!("{}", prefix);
+        for span in &left {
+            print!("{}", span.colored());
+        }
+        print!("{} {} ", " ".repeat(padding), "|".dimmed());
+        for span in &right {
+            print!("{}", span.colored());
+        }
+        println!();
+    }
+
+    /// Print the input line, highlighting the parts matching the query
+    /// ```
+    /// use ruplacer::{Query, replace};
+    /// let input = "let foo_bar = FooBar::new();";
+    /// let query = Query::subvert("foo_bar", "");
+    /// let replacement = replace(input, &query).unwrap();
+    /// replacement.print_match("foo.rs:3 ");
+    /// // outputs:
+    /// // foo.rs:3 let foo_bar = FooBar::new();
+    /// ```
+    pub fn print_match(&self, prefix: &str) {
+        print_spans(prefix, self.prepare_spans(self.input_spans()));
+    }
+
+    fn prepare_spans<'s>(&self, spans: Vec<Span<'s>>) -> Vec<Span<'s>> {
+        let spans = normalize_spans(spans);
+        match self.max_columns {
+            None => spans,
+            Some(max_columns) => elide_spans(spans, max_columns),
+        }
+    }
+
+    fn input_spans(&self) -> Vec<Span<'_>> {
+        let input_fragments = self.fragments.into_iter().map(|x| &x.0);
+        line_spans(self.input, input_fragments, SpanKind::Removed)
+    }
+
+    fn output_spans(&self) -> Vec<Span<'_>> {
+        let output_fragments = self.fragments.into_iter().map(|x| &x.1);
+        line_spans(&self.output, output_fragments, SpanKind::Added)
+    }
+
+    fn word_diff_spans(&self) -> Vec<Span<'_>> {
+        let mut spans = vec![];
         let mut current_index = 0;
-        for (i, fragment) in fragments.enumerate() {
-            let Fragment { index, text } = fragment;
-            // Whitespace between prefix and the first fragment does not matter
-            if i == 0 {
-                print!("{}", &line[current_index..*index].trim_start());
-            } else {
-                print!("{}", &line[current_index..*index]);
-            }
-            print!("{}", color(text));
+        for (input_fragment, output_fragment) in &self.fragments {
+            let Fragment { index, text } = input_fragment;
+            spans.push(Span::plain(&self.input[current_index..*index]));
+            spans.push(Span {
+                kind: SpanKind::Removed,
+                text: format!("[-{}-]", text).into(),
+            });
+            spans.push(Span {
+                kind: SpanKind::Added,
+                text: format!("{{+{}+}}", output_fragment.text).into(),
+            });
             current_index = index + text.len();
         }
-        print!("{}", &line[current_index..]);
-        if !line.ends_with('\n') {
-            println!()
+        spans.push(Span::plain(&self.input[current_index..]));
+        spans
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpanKind {
+    Plain,
+    Removed,
+    Added,
+    // Replaces text too far from the highlighted spans
+    Elided,
+}
+
+/// A part of a line to print, along with how to highlight it
+#[derive(Debug, PartialEq, Eq)]
+struct Span<'s> {
+    kind: SpanKind,
+    text: Cow<'s, str>,
+}
+
+impl<'s> Span<'s> {
+    fn plain(text: &'s str) -> Self {
+        Self {
+            kind: SpanKind::Plain,
+            text: text.into(),
+        }
+    }
+
+    fn colored(&self) -> ColoredString {
+        match self.kind {
+            SpanKind::Plain => self.text.normal(),
+            SpanKind::Removed => self.text.red().underline(),
+            SpanKind::Added => self.text.green().underline(),
+            SpanKind::Elided => self.text.dimmed(),
+        }
+    }
+}
+
+// Split the line into plain spans, and spans of the given kind for each fragment
+fn line_spans<'s>(
+    line: &'s str,
+    fragments: impl Iterator<Item = &'s Fragment>,
+    kind: SpanKind,
+) -> Vec<Span<'s>> {
+    let mut spans = vec![];
+    let mut current_index = 0;
+    for Fragment { index, text } in fragments {
+        spans.push(Span::plain(&line[current_index..*index]));
+        spans.push(Span {
+            kind,
+            text: text.as_str().into(),
+        });
+        current_index = index + text.len();
+    }
+    spans.push(Span::plain(&line[current_index..]));
+    spans
+}
+
+// Whitespace between the prefix and the first fragment does not matter,
+// and line endings are printed separately
+fn normalize_spans(mut spans: Vec<Span<'_>>) -> Vec<Span<'_>> {
+    if let Some(first) = spans.first_mut() {
+        if first.kind == SpanKind::Plain {
+            first.text = first.text.trim_start().to_string().into();
+        }
+    }
+    if let Some(last) = spans.last_mut() {
+        last.text = last
+            .text
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string()
+            .into();
+    }
+    spans.retain(|span| !span.text.is_empty());
+    spans
+}
+
+// Number of characters to always keep on each side of the highlighted spans
+const MIN_ELISION_MARGIN: usize = 10;
+
+// Replace the text of plain spans far from the highlighted spans with
+// a dimmed "…N chars…" marker, so that the line fits in about
+// `max_columns` characters
+fn elide_spans(spans: Vec<Span<'_>>, max_columns: usize) -> Vec<Span<'_>> {
+    if spans_width(&spans) <= max_columns {
+        return spans;
+    }
+    let is_highlighted = |span: &Span<'_>| span.kind != SpanKind::Plain;
+    // Note: plain spans between the first and the last highlighted spans
+    // have highlighted spans on both sides
+    let (first_highlighted, last_highlighted) = match (
+        spans.iter().position(is_highlighted),
+        spans.iter().rposition(is_highlighted),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return spans,
+    };
+    let num_highlighted = spans.iter().filter(|s| is_highlighted(s)).count();
+    let highlighted_width: usize = spans
+        .iter()
+        .filter(|s| s.kind != SpanKind::Plain)
+        .map(|s| s.text.chars().count())
+        .sum();
+    // There is at most one marker per plain span. Their width depends on the
+    // number of elided chars, which cannot exceed the length of the line
+    let marker_width = elision_marker(spans_width(&spans)).chars().count();
+    let markers_width = (num_highlighted + 1) * marker_width;
+    // Share the remaining columns between both sides of each highlighted span
+    let available = max_columns.saturating_sub(highlighted_width + markers_width);
+    let margin = (available / (2 * num_highlighted)).max(MIN_ELISION_MARGIN);
+    let mut res = vec![];
+    for (i, span) in spans.into_iter().enumerate() {
+        if span.kind != SpanKind::Plain {
+            res.push(span);
+            continue;
+        }
+        let keep_start = if i > first_highlighted { margin } else { 0 };
+        let keep_end = if i < last_highlighted { margin } else { 0 };
+        let chars: Vec<char> = span.text.chars().collect();
+        let num_elided = chars.len().saturating_sub(keep_start + keep_end);
+        let marker = elision_marker(num_elided);
+        // Only elide text if it makes the line shorter
+        if num_elided <= marker.chars().count() {
+            res.push(span);
+            continue;
         }
+        let start: String = chars[..keep_start].iter().collect();
+        let end: String = chars[chars.len() - keep_end..].iter().collect();
+        res.push(Span {
+            kind: SpanKind::Plain,
+            text: start.into(),
+        });
+        res.push(Span {
+            kind: SpanKind::Elided,
+            text: marker.into(),
+        });
+        res.push(Span {
+            kind: SpanKind::Plain,
+            text: end.into(),
+        });
     }
+    res.retain(|span| !span.text.is_empty());
+    res
+}
+
+fn elision_marker(num_elided: usize) -> String {
+    format!("…{} chars…", num_elided)
+}
+
+fn spans_width(spans: &[Span<'_>]) -> usize {
+    spans.iter().map(|span| span.text.chars().count()).sum()
+}
+
+// Cut the spans so that they fit in `width` characters, ending with '…' if
+// some text had to be removed
+fn fit_spans(spans: Vec<Span<'_>>, width: usize) -> Vec<Span<'_>> {
+    if spans_width(&spans) <= width {
+        return spans;
+    }
+    let mut res = vec![];
+    let mut available = width.saturating_sub(1);
+    for span in spans {
+        let num_chars = span.text.chars().count();
+        if num_chars <= available {
+            available -= num_chars;
+            res.push(span);
+            continue;
+        }
+        let text: String = span.text.chars().take(available).collect();
+        res.push(Span {
+            kind: span.kind,
+            text: text.into(),
+        });
+        break;
+    }
+    res.push(Span::plain("…"));
+    res
+}
+
+fn print_spans(prefix: &str, spans: Vec<Span<'_>>) {
+    print!("{}", prefix);
+    for span in &spans {
+        print!("{}", span.colored());
+    }
+    println!();
 }
 
 // A list of input_fragment, output_fragment
@@ -187,7 +478,7 @@ impl<'a> SubstringReplacer<'a> {
 
 impl<'a> Replacer for SubstringReplacer<'a> {
     fn replace(&self, buff: &str) -> Option<(usize, String, String)> {
-        let index = buff.find(&self.pattern)?;
+        let index = buff.find(self.pattern)?;
         Some((
             index,
             self.pattern.to_string(),
@@ -271,15 +562,15 @@ impl<'a> Replacer for RegexReplacer<'a> {
 fn get_fragments(input: &str, query: &Query) -> Fragments {
     match query {
         Query::Substring(pattern, replacement) => {
-            let finder = SubstringReplacer::new(&pattern, &replacement);
+            let finder = SubstringReplacer::new(pattern, replacement);
             get_fragments_with_finder(input, finder)
         }
         Query::Regex(regex, replacement) => {
-            let finder = RegexReplacer::new(&regex, &replacement);
+            let finder = RegexReplacer::new(regex, replacement);
             get_fragments_with_finder(input, finder)
         }
         Query::Subvert(items) => {
-            let finder = SubvertReplacer::new(&items);
+            let finder = SubvertReplacer::new(items);
             get_fragments_with_finder(input, finder)
         }
     }
@@ -325,7 +616,7 @@ fn get_output(input: &str, fragments: &Fragments) -> String {
         } = output_fragment;
 
         output.push_str(&input[current_index..*input_index]);
-        output.push_str(&output_text);
+        output.push_str(output_text);
         current_index = input_index + input_text.len();
     }
     output.push_str(&input[current_index..]);
@@ -373,6 +664,38 @@ mod tests {
         replacement.print_self("foo.txt:3 ");
     }
 
+    #[test]
+    fn test_retain_fragments() {
+        let input = "old is old, old is old";
+        let query = Query::substring("old", "brand new");
+        let replacement = replace(input, &query).unwrap();
+        let mut i = 0;
+        let replacement = replacement
+            .retain_fragments(|_| {
+                i += 1;
+                i % 2 == 0
+            })
+            .unwrap();
+        assert_eq!(replacement.num_fragments(), 2);
+        assert_eq!(replacement.output(), "old is brand new, old is brand new");
+        let output_indexes: Vec<_> = replacement
+            .fragments
+            .into_iter()
+            .map(|(_, output_fragment)| output_fragment.index)
+            .collect();
+        assert_eq!(output_indexes, vec![7, 25]);
+
+        let replacement = replace(input, &query).unwrap();
+        let replacement = replacement.retain_fragments(|range| range.start > 10);
+        assert_eq!(
+            replacement.unwrap().output(),
+            "old is old, brand new is brand new"
+        );
+
+        let replacement = replace(input, &query).unwrap();
+        assert!(replacement.retain_fragments(|_| false).is_none());
+    }
+
     #[test]
     fn test_subvert() {
         let input = "let foo_bar = FooBar::new();";
@@ -400,4 +723,183 @@ mod tests {
         let replacement = replace(input, &query).unwrap();
         assert_eq!(replacement.output(), "new is new");
     }
+
+    fn span_texts(spans: &[Span]) -> Vec<String> {
+        spans.iter().map(|span| span.text.to_string()).collect()
+    }
+
+    #[test]
+    fn test_word_diff_spans() {
+        let input = "  old is old\n";
+        let query = Query::substring("old", "new");
+        let replacement = replace(input, &query).unwrap();
+        let spans = normalize_spans(replacement.word_diff_spans());
+        assert_eq!(
+            span_texts(&spans),
+            ["[-old-]", "{+new+}", " is ", "[-old-]", "{+new+}"]
+        );
+    }
+
+    #[test]
+    fn test_fit_spans() {
+        let input = "old is old";
+        let query = Query::substring("old", "new");
+        let replacement = replace(input, &query).unwrap();
+
+        let spans = fit_spans(replacement.input_spans(), 10);
+        assert_eq!(span_texts(&spans), ["", "old", " is ", "old", ""]);
+
+        let spans = fit_spans(replacement.input_spans(), 6);
+        assert_eq!(spans_width(&spans), 6);
+        assert_eq!(span_texts(&spans), ["", "old", " i", "…"]);
+        assert_eq!(spans[1].kind, SpanKind::Removed);
+
+        // Exactly one column short: the second match is cut
+        let spans = fit_spans(replacement.input_spans(), 9);
+        assert_eq!(spans_width(&spans), 9);
+        assert_eq!(span_texts(&spans), ["", "old", " is ", "o", "…"]);
+        assert_eq!(spans[3].kind, SpanKind::Removed);
+    }
+
+    #[test]
+    fn test_fit_long_spans() {
+        let input = format!(
+            "{}old{}old{}",
+            "a".repeat(50),
+            "b".repeat(50),
+            "c".repeat(50)
+        );
+        let query = Query::substring("old", "new");
+        let replacement = replace(&input, &query).unwrap();
+
+        let spans = fit_spans(replacement.input_spans(), 80);
+        assert_eq!(spans_width(&spans), 80);
+        assert_eq!(
+            span_texts(&spans),
+            [
+                "a".repeat(50),
+                "old".to_string(),
+                "b".repeat(26),
+                "…".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elide_spans() {
+        let input = format!(
+            "{}old{}old{}",
+            "a".repeat(100),
+            "b".repeat(100),
+            "c".repeat(5)
+        );
+        let query = Query::substring("old", "new");
+        let mut replacement = replace(&input, &query).unwrap();
+        replacement.set_max_columns(Some(46));
+        let spans = replacement.prepare_spans(replacement.input_spans());
+        // 46 columns, minus 6 for the fragments, shared between 4 sides
+        let margin = "a".repeat(10);
+        assert_eq!(
+            span_texts(&spans),
+            [
+                "…90 chars…",
+                &margin,
+                "old",
+                &"b".repeat(10),
+                "…80 chars…",
+                &"b".repeat(10),
+                "old",
+                "ccccc"
+            ]
+        );
+        assert_eq!(spans[0].kind, SpanKind::Elided);
+    }
+
+    #[test]
+    fn test_elide_spans_at_max_columns() {
+        let input = format!("{}old{}", "a".repeat(50), "b".repeat(50));
+        let query = Query::substring("old", "new");
+        let mut replacement = replace(&input, &query).unwrap();
+
+        // The line fits exactly, so nothing is elided
+        replacement.set_max_columns(Some(103));
+        let spans = replacement.prepare_spans(replacement.input_spans());
+        assert_eq!(
+            span_texts(&spans),
+            ["a".repeat(50), "old".to_string(), "b".repeat(50)]
+        );
+
+        // One column short: 102 columns, minus 3 for the fragment and 2 * 11
+        // for the markers, shared between 2 sides
+        replacement.set_max_columns(Some(102));
+        let spans = replacement.prepare_spans(replacement.input_spans());
+        assert_eq!(
+            span_texts(&spans),
+            [
+                "…12 chars…".to_string(),
+                "a".repeat(38),
+                "old".to_string(),
+                "b".repeat(38),
+                "…12 chars…".to_string(),
+            ]
+        );
+        assert_eq!(spans_width(&spans), 99);
+
+        // 80 columns, minus 3 for the fragment and 2 * 11 for the markers,
+        // shared between 2 sides
+        replacement.set_max_columns(Some(80));
+        let spans = replacement.prepare_spans(replacement.input_spans());
+        assert_eq!(
+            span_texts(&spans),
+            [
+                "…23 chars…".to_string(),
+                "a".repeat(27),
+                "old".to_string(),
+                "b".repeat(27),
+                "…23 chars…".to_string(),
+            ]
+        );
+        assert_eq!(spans_width(&spans), 77);
+    }
+
+    #[test]
+    fn test_elide_spans_with_several_highlights() {
+        let input = format!(
+            "{}old{}old{}old{}",
+            "a".repeat(100),
+            "b".repeat(100),
+            "c".repeat(100),
+            "d".repeat(100)
+        );
+        let query = Query::substring("old", "new");
+        let mut replacement = replace(&input, &query).unwrap();
+        // 129 columns, minus 9 for the fragments and 4 * 11 for the markers,
+        // shared between 6 sides
+        replacement.set_max_columns(Some(129));
+        let spans = replacement.prepare_spans(replacement.input_spans());
+        assert_eq!(
+            span_texts(&spans),
+            [
+                "…88 chars…".to_string(),
+                "a".repeat(12),
+                "old".to_string(),
+                "b".repeat(12),
+                "…76 chars…".to_string(),
+                "b".repeat(12),
+                "old".to_string(),
+                "c".repeat(12),
+                "…76 chars…".to_string(),
+                "c".repeat(12),
+                "old".to_string(),
+                "d".repeat(12),
+                "…88 chars…".to_string(),
+            ]
+        );
+        assert_eq!(spans_width(&spans), 121);
+        let kinds: Vec<_> = spans.iter().map(|span| span.kind).collect();
+        assert_eq!(kinds[0], SpanKind::Elided);
+        assert_eq!(kinds[2], SpanKind::Removed);
+        assert_eq!(kinds[6], SpanKind::Removed);
+        assert_eq!(kinds[10], SpanKind::Removed);
+    }
 }
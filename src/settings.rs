@@ -1,5 +1,135 @@
-#[derive(Debug)]
-/// Settings applied for a DirectoryPatcher run
+use crate::error::Error;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+/// What to print about each matching file
+pub enum OutputFormat {
+    /// Print every matching line (default)
+    #[default]
+    Lines,
+    /// Print the path of each matching file, followed by its number of matches
+    Count,
+    /// Only print the path of each matching file
+    FilesWithMatches,
+    /// Print the path of each file to patch, followed by its number of
+    /// replacements, then the statistics at the end of the run
+    FilesOnly,
+    /// Do not print anything about each matching file, only the
+    /// statistics gathered in `Stats` at the end of the run
+    StatsOnly,
+    /// Do not print anything
+    Quiet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+/// Which parts of the source files to patch. Restricting the scope only
+/// works for the languages ruplacer knows about: C, C++, Go, Java,
+/// JavaScript, TypeScript, Python and Rust
+pub enum Scope {
+    /// Patch everything (default)
+    #[default]
+    All,
+    /// Only patch code, leaving comments and string literals untouched
+    Code,
+    /// Only patch comments
+    Comments,
+    /// Only patch string literals
+    Strings,
+    /// Only patch whole identifiers, such as variable, function or type names.
+    /// Requires the `tree-sitter` feature
+    Identifiers,
+}
+
+impl std::str::FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Scope, Error> {
+        match s {
+            "all" => Ok(Scope::All),
+            "code" => Ok(Scope::Code),
+            "comments" => Ok(Scope::Comments),
+            "strings" => Ok(Scope::Strings),
+            "identifiers" => Ok(Scope::Identifiers),
+            _ => Err(Error::InvalidValue {
+                value: s.to_string(),
+                expected: "Choose between 'all', 'code', 'comments', 'strings', or 'identifiers'",
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+/// How to print each replacement
+pub enum DiffStyle {
+    /// Print the old line in red, then the new line in green (default)
+    #[default]
+    Lines,
+    /// Print a single line, like `git diff --word-diff`: `[-old-]{+new+}`
+    Word,
+    /// Print the old line and the new line next to each other, fitting
+    /// the terminal width
+    SideBySide,
+}
+
+impl std::str::FromStr for DiffStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DiffStyle, Error> {
+        match s {
+            "lines" => Ok(DiffStyle::Lines),
+            "word" => Ok(DiffStyle::Word),
+            "side-by-side" => Ok(DiffStyle::SideBySide),
+            _ => Err(Error::InvalidValue {
+                value: s.to_string(),
+                expected: "Choose between 'lines', 'word', or 'side-by-side'",
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "kind", content = "revision", rename_all = "kebab-case")
+)]
+/// Which files to patch, according to git
+pub enum GitSelection {
+    /// Only patch files tracked by git
+    Tracked,
+    /// Only patch files modified relative to the given revision, including
+    /// uncommitted changes
+    ChangedSince(String),
+    /// Only patch files with staged changes
+    Staged,
+}
+
+#[derive(Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
+/// Settings applied for a DirectoryPatcher run.
+///
+/// New settings may be added in any release, so use `Settings::builder()`
+/// or `Settings::default()` to create them
 pub struct Settings {
     /// If true, do not write changes to the file system (default: false)
     pub dry_run: bool,
@@ -7,20 +137,252 @@ pub struct Settings {
     pub hidden: bool,
     /// If true, also patch ignored files (default: false)
     pub ignored: bool,
+    /// If true, do not honor `.ruplacerignore` files (default: false)
+    pub no_ruplacerignore: bool,
+    /// If true, apply the file types, globs, hidden files and file size filters
+    /// to the files given to `DirectoryPatcher::with_files` (default: false)
+    pub filter_listed_files: bool,
     /// List of file types to select (default: empty)
     pub selected_file_types: Vec<String>,
     /// List of file types to ignore (default: empty)
     pub ignored_file_types: Vec<String>,
+    /// Only patch paths matching one of these globs, relative to the source path.
+    /// Note that they take precedence over the ignore rules (default: empty)
+    pub included_globs: Vec<String>,
+    /// Never patch paths matching one of these globs, relative to the source path (default: empty)
+    pub excluded_globs: Vec<String>,
+    /// Maximum depth of the directories to walk, 0 being the source path (default: None, no limit)
+    pub max_depth: Option<usize>,
+    /// If true, follow symbolic links. Loops are reported and skipped (default: false)
+    pub follow_links: bool,
+    /// If true, do not walk into directories on other file systems (default: false)
+    pub one_file_system: bool,
+    /// Skip files larger than this size, in bytes (default: None, no limit)
+    pub max_filesize: Option<u64>,
+    /// If set, only patch the files selected by git (default: None)
+    pub git_selection: Option<GitSelection>,
+    /// If true, write to files even if they have uncommitted changes in git,
+    /// are not tracked, or are not in a git repository (default: false)
+    pub allow_dirty: bool,
+    /// If true, only print the matches and never write to the file system (default: false)
+    pub search_only: bool,
+    /// What to print about each matching file (default: OutputFormat::Lines)
+    pub output_format: OutputFormat,
+    /// How to print each replacement (default: DiffStyle::Lines)
+    pub diff_style: DiffStyle,
+    /// If set, elide the text far from the matches when printing lines longer
+    /// than this number of characters (default: None, print whole lines)
+    pub max_columns: Option<usize>,
+    /// Number of lines to print before each matching line (default: 0)
+    pub context_before: usize,
+    /// Number of lines to print after each matching line (default: 0)
+    pub context_after: usize,
+    /// Maximum number of replacements in each file (default: None, no limit)
+    pub max_replacements_per_file: Option<usize>,
+    /// Maximum number of replacements in each line (default: None, no limit)
+    pub max_replacements_per_line: Option<usize>,
+    /// Only replace the matches at these positions in each file, starting at 1
+    /// (default: empty, replace every match)
+    pub occurrences: Vec<usize>,
+    /// Only patch lines in these ranges, starting at 1 (default: empty, patch every line)
+    pub line_ranges: Vec<RangeInclusive<usize>>,
+    /// If set, skip files not containing a match for this regex (default: None)
+    #[cfg_attr(feature = "serde", serde(with = "optional_regex"))]
+    pub if_contains: Option<regex::Regex>,
+    /// If set, skip files containing a match for this regex (default: None)
+    #[cfg_attr(feature = "serde", serde(with = "optional_regex"))]
+    pub unless_contains: Option<regex::Regex>,
+    /// If set, only patch lines matching this regex (default: None)
+    #[cfg_attr(feature = "serde", serde(with = "optional_regex"))]
+    pub only_lines: Option<regex::Regex>,
+    /// If set, do not patch lines matching this regex (default: None)
+    #[cfg_attr(feature = "serde", serde(with = "optional_regex"))]
+    pub skip_lines: Option<regex::Regex>,
+    /// Which parts of the source files to patch (default: Scope::All)
+    pub scope: Scope,
 }
 
-impl Default for Settings {
-    fn default() -> Self {
-        Self {
-            dry_run: false,
-            hidden: false,
-            ignored: false,
-            selected_file_types: vec![],
-            ignored_file_types: vec![],
+// Store regexes as their pattern
+#[cfg(feature = "serde")]
+mod optional_regex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        regex: &Option<regex::Regex>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match regex {
+            Some(regex) => serializer.serialize_some(regex.as_str()),
+            None => serializer.serialize_none(),
         }
     }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<regex::Regex>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|pattern| regex::Regex::new(&pattern).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+impl Settings {
+    /// Return a builder for Settings, starting from the default values
+    ///
+    /// ```rust
+    /// use ruplacer::{OutputFormat, Settings};
+    ///
+    /// let settings = Settings::builder()
+    ///     .hidden(true)
+    ///     .types(["rust", "*.toml"])
+    ///     .exclude(["vendor/**"])
+    ///     .max_depth(3)
+    ///     .output_format(OutputFormat::Count)
+    ///     .build();
+    /// assert_eq!(settings.selected_file_types, vec!["rust", "*.toml"]);
+    /// ```
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+}
+
+// Generate a setter for each given field. Setters for optional fields
+// accept either a value or an Option
+macro_rules! setters {
+    ($($field:ident: $type:ty,)*) => {
+        $(
+            #[doc = concat!("Set `Settings::", stringify!($field), "`")]
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.settings.$field = $field.into();
+                self
+            }
+        )*
+    };
+}
+
+#[derive(Debug, Default)]
+/// Builds Settings, see `Settings::builder()`.
+///
+/// Setters are named after the fields of Settings, except for the file types
+/// and globs filters, which are named after their command line options
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+impl SettingsBuilder {
+    setters! {
+        dry_run: bool,
+        hidden: bool,
+        ignored: bool,
+        no_ruplacerignore: bool,
+        filter_listed_files: bool,
+        max_depth: impl Into<Option<usize>>,
+        follow_links: bool,
+        one_file_system: bool,
+        max_filesize: impl Into<Option<u64>>,
+        git_selection: impl Into<Option<GitSelection>>,
+        allow_dirty: bool,
+        search_only: bool,
+        output_format: OutputFormat,
+        diff_style: DiffStyle,
+        max_columns: impl Into<Option<usize>>,
+        context_before: usize,
+        context_after: usize,
+        max_replacements_per_file: impl Into<Option<usize>>,
+        max_replacements_per_line: impl Into<Option<usize>>,
+        if_contains: impl Into<Option<regex::Regex>>,
+        unless_contains: impl Into<Option<regex::Regex>>,
+        only_lines: impl Into<Option<regex::Regex>>,
+        skip_lines: impl Into<Option<regex::Regex>>,
+        scope: Scope,
+    }
+
+    /// Set `Settings::selected_file_types`, like `--type`
+    pub fn types(mut self, types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.settings.selected_file_types = types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set `Settings::ignored_file_types`, like `--type-not`
+    pub fn types_not(mut self, types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.settings.ignored_file_types = types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set `Settings::included_globs`, like `--include`
+    pub fn include(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.settings.included_globs = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set `Settings::excluded_globs`, like `--exclude`
+    pub fn exclude(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.settings.excluded_globs = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set `Settings::occurrences`
+    pub fn occurrences(mut self, occurrences: impl IntoIterator<Item = usize>) -> Self {
+        self.settings.occurrences = occurrences.into_iter().collect();
+        self
+    }
+
+    /// Set `Settings::line_ranges`
+    pub fn line_ranges(
+        mut self,
+        line_ranges: impl IntoIterator<Item = RangeInclusive<usize>>,
+    ) -> Self {
+        self.settings.line_ranges = line_ranges.into_iter().collect();
+        self
+    }
+
+    /// Return the Settings. Fields that were not set keep their default value
+    pub fn build(self) -> Settings {
+        self.settings
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_settings() {
+        let settings = Settings::builder()
+            .hidden(true)
+            .types(["rust"])
+            .git_selection(GitSelection::ChangedSince("origin/main".to_string()))
+            .output_format(OutputFormat::FilesWithMatches)
+            .line_ranges([2..=4])
+            .skip_lines(regex::Regex::new("^#").unwrap())
+            .scope(Scope::Comments)
+            .build();
+        let serialized = serde_json::to_string(&settings).unwrap();
+        let actual: Settings = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(actual.selected_file_types, vec!["rust"]);
+        assert_eq!(
+            actual.git_selection,
+            Some(GitSelection::ChangedSince("origin/main".to_string()))
+        );
+        assert_eq!(actual.output_format, OutputFormat::FilesWithMatches);
+        assert_eq!(actual.line_ranges, vec![2..=4]);
+        assert_eq!(actual.skip_lines.unwrap().as_str(), "^#");
+        assert_eq!(actual.scope, Scope::Comments);
+    }
+
+    #[test]
+    fn test_serialize_default_settings() {
+        let serialized = toml::to_string(&Settings::default()).unwrap();
+        let actual: Settings = toml::from_str(&serialized).unwrap();
+        assert_eq!(actual.output_format, OutputFormat::Lines);
+        assert!(actual.git_selection.is_none());
+    }
+
+    #[test]
+    fn test_deserialize_partial_settings() {
+        let settings: Settings = toml::from_str("hidden = true\nscope = \"code\"\n").unwrap();
+        assert!(settings.hidden);
+        assert_eq!(settings.scope, Scope::Code);
+        assert!(!settings.dry_run);
+    }
 }
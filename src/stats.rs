@@ -1,4 +1,167 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::types::TypesBuilder;
 use inflector::string::pluralize::to_plural;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Statistics about one matching file
+pub struct FileStats {
+    pub(crate) path: PathBuf,
+    pub(crate) lines: usize,
+    pub(crate) replacements: usize,
+    pub(crate) skipped_matches: usize,
+    pub(crate) bytes_before: usize,
+    pub(crate) bytes_after: usize,
+}
+
+impl FileStats {
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of lines that were replaced
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Number of replacements
+    pub fn replacements(&self) -> usize {
+        self.replacements
+    }
+
+    /// Number of matches that were left untouched because of the settings
+    pub fn skipped_matches(&self) -> usize {
+        self.skipped_matches
+    }
+
+    /// Size of the file before the replacements, in bytes
+    pub fn bytes_before(&self) -> usize {
+        self.bytes_before
+    }
+
+    /// Size of the file after the replacements, in bytes
+    pub fn bytes_after(&self) -> usize {
+        self.bytes_after
+    }
+}
+
+// Match file names against the same definitions as `--type`, so that
+// `Makefile` and `foo.mk` are both counted as "make" files
+struct FileTypes {
+    names: Vec<String>,
+    // Index in `names` of the type each glob comes from
+    owners: Vec<usize>,
+    globs: GlobSet,
+}
+
+impl FileTypes {
+    fn new() -> Self {
+        let mut types_builder = TypesBuilder::new();
+        types_builder.add_defaults();
+        let mut definitions = types_builder.definitions();
+        definitions.sort_by(|a, b| a.name().cmp(b.name()));
+
+        let mut names = vec![];
+        let mut owners = vec![];
+        let mut globs = GlobSetBuilder::new();
+        for (index, definition) in definitions.iter().enumerate() {
+            names.push(definition.name().to_string());
+            for glob in definition.globs() {
+                // Note: the default definitions are always valid
+                globs.add(Glob::new(glob).expect("invalid default file type"));
+                owners.push(index);
+            }
+        }
+        let globs = globs.build().expect("invalid default file types");
+        Self {
+            names,
+            owners,
+            globs,
+        }
+    }
+
+    /// Names of all the types matching the given file, like "c" and "cpp"
+    /// for `foo.h`, or a single empty string if no known type matches
+    fn names(&self, path: &Path) -> Vec<&str> {
+        let matches = match path.file_name() {
+            Some(file_name) => self.globs.matches(file_name),
+            None => vec![],
+        };
+        let mut indexes: Vec<_> = matches.iter().map(|&i| self.owners[i]).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        if indexes.is_empty() {
+            return vec![""];
+        }
+        indexes.iter().map(|&i| self.names[i].as_str()).collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// Statistics about all the matching files of the same type
+pub struct FileTypeStats {
+    file_type: String,
+    files: usize,
+    lines: usize,
+    replacements: usize,
+    skipped_matches: usize,
+    bytes_before: usize,
+    bytes_after: usize,
+}
+
+impl FileTypeStats {
+    /// Name of the file type, as given to `--type`, or an empty string
+    /// for files matching no known type. See `ruplacer --type-list`
+    pub fn file_type(&self) -> &str {
+        &self.file_type
+    }
+
+    /// Number of matching files
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Number of lines that were replaced
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Number of replacements
+    pub fn replacements(&self) -> usize {
+        self.replacements
+    }
+
+    /// Number of matches that were left untouched because of the settings
+    pub fn skipped_matches(&self) -> usize {
+        self.skipped_matches
+    }
+
+    /// Size of the files before the replacements, in bytes
+    pub fn bytes_before(&self) -> usize {
+        self.bytes_before
+    }
+
+    /// Size of the files after the replacements, in bytes
+    pub fn bytes_after(&self) -> usize {
+        self.bytes_after
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Summary of a run of DirectoryPatcher
+pub enum Outcome {
+    /// At least one replacement was found
+    Found,
+    /// No replacement was found
+    NothingFound,
+    /// Some files or directories could not be read. Replacements may
+    /// have been found in the others
+    PartialErrors,
+}
 
 #[derive(Default, Debug)]
 /// Statistics about a run of DirectoryPatcher
@@ -6,16 +169,51 @@ pub struct Stats {
     matching_files: usize,
     matching_lines: usize,
     total_replacements: usize,
+    skipped_matches: usize,
+    skipped_files: usize,
+    errors: Vec<Error>,
+    warnings: Vec<Error>,
+    files: Vec<FileStats>,
 }
 
 impl Stats {
-    pub(crate) fn update(&mut self, lines: usize, replacements: usize) {
+    pub(crate) fn update(&mut self, file_stats: FileStats) {
+        if file_stats.replacements + file_stats.skipped_matches == 0 {
+            return;
+        }
         self.matching_files += 1;
-        self.matching_lines += lines;
-        self.total_replacements += replacements;
+        self.matching_lines += file_stats.lines;
+        self.total_replacements += file_stats.replacements;
+        self.skipped_matches += file_stats.skipped_matches;
+        self.files.push(file_stats);
     }
 
-    /// Number of matching files
+    pub(crate) fn skip_file(&mut self) {
+        self.skipped_files += 1;
+    }
+
+    pub(crate) fn add_error(&mut self, error: Error) {
+        self.errors.push(error);
+    }
+
+    pub(crate) fn add_warning(&mut self, warning: Error) {
+        self.warnings.push(warning);
+    }
+
+    /// Summary of the run
+    pub fn outcome(&self) -> Outcome {
+        if !self.errors.is_empty() {
+            Outcome::PartialErrors
+        } else if self.total_replacements == 0 {
+            Outcome::NothingFound
+        } else {
+            Outcome::Found
+        }
+    }
+
+    /// Number of files containing at least one match. Files where every
+    /// match was left untouched because of the settings are included, but
+    /// files without any match are not
     pub fn matching_files(&self) -> usize {
         self.matching_files
     }
@@ -29,6 +227,140 @@ impl Stats {
     pub fn total_replacements(&self) -> usize {
         self.total_replacements
     }
+
+    /// Number of matches that were left untouched because of the settings
+    pub fn skipped_matches(&self) -> usize {
+        self.skipped_matches
+    }
+
+    /// Number of matching files that were left untouched because of the settings
+    pub fn skipped_files(&self) -> usize {
+        self.skipped_files
+    }
+
+    /// Why some files or directories could not be read. They were skipped
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Problems that did not prevent the run, such as symbolic links pointing to
+    /// one of their parent directories, or paths that could not be checked for
+    /// uncommitted changes because they are not in a git repository
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    /// Statistics about each matching file, in the order they were found
+    pub fn files(&self) -> &[FileStats] {
+        &self.files
+    }
+
+    /// Statistics about the matching files, aggregated by file type
+    /// and sorted by name.
+    ///
+    /// The types are the ones used by `--type`. A file matching several
+    /// of them, like `foo.h` for "c" and "cpp", is counted in each, so the
+    /// totals of all the types can exceed the ones of the whole run
+    pub fn file_types(&self) -> Vec<FileTypeStats> {
+        let types = FileTypes::new();
+        let mut by_type: BTreeMap<String, FileTypeStats> = BTreeMap::new();
+        for file in &self.files {
+            for file_type in types.names(&file.path) {
+                let entry = by_type
+                    .entry(file_type.to_string())
+                    .or_insert_with(|| FileTypeStats {
+                        file_type: file_type.to_string(),
+                        ..Default::default()
+                    });
+                entry.files += 1;
+                entry.lines += file.lines;
+                entry.replacements += file.replacements;
+                entry.skipped_matches += file.skipped_matches;
+                entry.bytes_before += file.bytes_before;
+                entry.bytes_after += file.bytes_after;
+            }
+        }
+        by_type.into_values().collect()
+    }
+
+    /// Render the per-file and per-file type statistics as two tables
+    pub fn breakdown(&self) -> String {
+        let headers = [
+            "lines",
+            "replacements",
+            "skipped",
+            "bytes before",
+            "bytes after",
+        ];
+        let file_rows = self.files.iter().map(|f| {
+            let name = f.path.display().to_string();
+            (
+                name,
+                [
+                    f.lines,
+                    f.replacements,
+                    f.skipped_matches,
+                    f.bytes_before,
+                    f.bytes_after,
+                ],
+            )
+        });
+        let type_rows = self.file_types().into_iter().map(|t| {
+            let name = if t.file_type.is_empty() {
+                "(none)".to_string()
+            } else {
+                t.file_type
+            };
+            (
+                name,
+                [
+                    t.lines,
+                    t.replacements,
+                    t.skipped_matches,
+                    t.bytes_before,
+                    t.bytes_after,
+                ],
+            )
+        });
+        let mut res = format_table("file", &headers, file_rows.collect());
+        res.push('\n');
+        res.push_str(&format_table("type", &headers, type_rows.collect()));
+        res
+    }
+}
+
+// Left-align the first column, and right-align the numbers. Each column
+// is as wide as its header or its widest cell, whichever is larger
+fn format_table<const N: usize>(
+    first_header: &str,
+    headers: &[&str; N],
+    rows: Vec<(String, [usize; N])>,
+) -> String {
+    let first_width = rows
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .chain(std::iter::once(first_header.len()))
+        .max()
+        .unwrap_or_default();
+    let mut widths = headers.map(str::len);
+    for (_, numbers) in &rows {
+        for (width, number) in widths.iter_mut().zip(numbers.iter()) {
+            *width = (*width).max(number.to_string().len());
+        }
+    }
+    let mut res = format!("{:<width$}", first_header, width = first_width);
+    for (header, width) in headers.iter().zip(widths.iter()) {
+        res.push_str(&format!("  {:>width$}", header, width = width));
+    }
+    res.push('\n');
+    for (name, numbers) in rows {
+        res.push_str(&format!("{:<width$}", name, width = first_width));
+        for (number, width) in numbers.iter().zip(widths.iter()) {
+            res.push_str(&format!("  {:>width$}", number, width = width));
+        }
+        res.push('\n');
+    }
+    res
 }
 
 fn pluralize(input: &str, num: usize) -> String {
@@ -47,7 +379,24 @@ impl std::fmt::Display for Stats {
             f,
             "{} {} on {} matching {}",
             self.total_replacements, replacements_string, self.matching_files, file_string
-        )
+        )?;
+        let mut skipped = vec![];
+        if self.skipped_matches != 0 {
+            let match_string = pluralize("match", self.skipped_matches);
+            skipped.push(format!("{} skipped {}", self.skipped_matches, match_string));
+        }
+        if self.skipped_files != 0 {
+            let file_string = pluralize("file", self.skipped_files);
+            skipped.push(format!("{} skipped {}", self.skipped_files, file_string));
+        }
+        if !self.errors.is_empty() {
+            let error_string = pluralize("error", self.errors.len());
+            skipped.push(format!("{} {}", self.errors.len(), error_string));
+        }
+        if !skipped.is_empty() {
+            write!(f, " ({})", skipped.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -61,6 +410,9 @@ mod tests {
             matching_files: 2,
             total_replacements: 4,
             matching_lines: 1,
+            skipped_matches: 0,
+            skipped_files: 0,
+            ..Default::default()
         };
         let actual = stats.to_string();
         assert_eq!(actual, "4 replacements on 2 matching files");
@@ -69,8 +421,132 @@ mod tests {
             matching_files: 1,
             total_replacements: 2,
             matching_lines: 1,
+            skipped_matches: 0,
+            skipped_files: 0,
+            ..Default::default()
         };
         let actual = stats.to_string();
         assert_eq!(actual, "2 replacements on 1 matching file");
+
+        let stats = Stats {
+            matching_files: 1,
+            total_replacements: 2,
+            matching_lines: 1,
+            skipped_matches: 3,
+            skipped_files: 1,
+            errors: vec![
+                Error::Encoding {
+                    path: PathBuf::from("foo.txt"),
+                },
+                Error::Encoding {
+                    path: PathBuf::from("bar.txt"),
+                },
+            ],
+            ..Default::default()
+        };
+        let actual = stats.to_string();
+        assert_eq!(
+            actual,
+            "2 replacements on 1 matching file (3 skipped matches, 1 skipped file, 2 errors)"
+        );
+        assert_eq!(stats.outcome(), Outcome::PartialErrors);
+    }
+
+    fn file_stats(path: &str, replacements: usize) -> FileStats {
+        FileStats {
+            path: PathBuf::from(path),
+            lines: 1,
+            replacements,
+            skipped_matches: 0,
+            bytes_before: 10,
+            bytes_after: 12,
+        }
+    }
+
+    #[test]
+    fn test_matching_files() {
+        let mut stats = Stats::default();
+        stats.update(file_stats("foo.txt", 2));
+        stats.update(file_stats("no_match.txt", 0));
+        stats.update(FileStats {
+            skipped_matches: 3,
+            ..file_stats("skipped.txt", 0)
+        });
+
+        assert_eq!(stats.matching_files(), 2);
+        assert_eq!(stats.total_replacements(), 2);
+        assert_eq!(stats.skipped_matches(), 3);
+        let paths: Vec<_> = stats.files().iter().map(|f| f.path()).collect();
+        assert_eq!(paths, [Path::new("foo.txt"), Path::new("skipped.txt")]);
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let mut stats = Stats::default();
+        stats.update(file_stats("src/foo.c", 2));
+        stats.update(file_stats("Makefile", 1));
+        stats.update(file_stats("src/bar.c", 1));
+        stats.update(file_stats("GNUmakefile", 1));
+        stats.update(file_stats("data.bin", 1));
+        stats.update(file_stats("README.md", 0));
+
+        let file_types = stats.file_types();
+        let names: Vec<_> = file_types.iter().map(|t| t.file_type()).collect();
+        assert_eq!(names, ["", "c", "make"]);
+        assert_eq!(file_types[1].files(), 2);
+        assert_eq!(file_types[1].replacements(), 3);
+        assert_eq!(file_types[2].files(), 2);
+
+        let expected = "\
+file         lines  replacements  skipped  bytes before  bytes after
+src/foo.c        1             2        0            10           12
+Makefile         1             1        0            10           12
+src/bar.c        1             1        0            10           12
+GNUmakefile      1             1        0            10           12
+data.bin         1             1        0            10           12
+
+type    lines  replacements  skipped  bytes before  bytes after
+(none)      1             1        0            10           12
+c           2             3        0            20           24
+make        2             2        0            20           24
+";
+        assert_eq!(stats.breakdown(), expected);
+    }
+
+    #[test]
+    fn test_file_matching_several_types() {
+        let mut stats = Stats::default();
+        stats.update(file_stats("foo.c", 1));
+        stats.update(file_stats("foo.h", 1));
+
+        let file_types = stats.file_types();
+        let c_stats = file_types.iter().find(|t| t.file_type() == "c").unwrap();
+        assert_eq!(c_stats.files(), 2);
+        let cpp_stats = file_types.iter().find(|t| t.file_type() == "cpp").unwrap();
+        assert_eq!(cpp_stats.files(), 1);
+    }
+
+    #[test]
+    fn test_breakdown_with_wide_numbers() {
+        let mut stats = Stats::default();
+        stats.update(FileStats {
+            skipped_matches: 3,
+            bytes_before: 123_456_789_012_345,
+            ..file_stats("foo.rs", 1)
+        });
+        stats.update(file_stats("bar.rs", 1));
+
+        let file_types = stats.file_types();
+        assert_eq!(file_types[0].skipped_matches(), 3);
+
+        let expected = "\
+file    lines  replacements  skipped     bytes before  bytes after
+foo.rs      1             1        3  123456789012345           12
+bar.rs      1             1        0               10           12
+
+type  lines  replacements  skipped     bytes before  bytes after
+rust      2             2        3  123456789012355           24
+";
+        assert_eq!(stats.breakdown(), expected);
     }
 }
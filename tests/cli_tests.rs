@@ -0,0 +1,455 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use tempdir::TempDir;
+
+// Run the ruplacer binary in the given directory, without reading
+// any configuration file. Note: the temporary directories are not in a
+// git repository, so files can only be written with --allow-dirty
+fn run_ruplacer(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ruplacer"))
+        .current_dir(dir)
+        .args(["--color", "never", "--no-config", "--allow-dirty"])
+        .args(args)
+        .output()
+        .expect("could not run ruplacer")
+}
+
+// Same as `run_ruplacer`, but check the files for uncommitted changes
+fn run_ruplacer_checking_git(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ruplacer"))
+        .current_dir(dir)
+        .args(["--color", "never", "--no-config"])
+        .args(args)
+        .output()
+        .expect("could not run ruplacer")
+}
+
+// Run the ruplacer binary in the given directory, reading the project
+// configuration files and the user configuration file in `config_home`
+fn run_ruplacer_with_config(dir: &Path, config_home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ruplacer"))
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", config_home)
+        .env("APPDATA", config_home)
+        .args(["--color", "never", "--allow-dirty"])
+        .args(args)
+        .output()
+        .expect("could not run ruplacer")
+}
+
+// Run the ruplacer binary on the given input, read from stdin
+fn run_ruplacer_on_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ruplacer"))
+        .args(["--color", "never", "--no-config"])
+        .args(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run ruplacer");
+    let mut stdin = child.stdin.take().unwrap();
+    // Note: ruplacer may exit without reading its input, for instance
+    // when some options cannot be used with stdin
+    let _ = stdin.write_all(input.as_bytes());
+    drop(stdin);
+    child.wait_with_output().expect("could not run ruplacer")
+}
+
+fn setup_file(tmp_dir: &TempDir, contents: &str) -> std::path::PathBuf {
+    let path = tmp_dir.path().join("foo.txt");
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn test_ignore_case() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "Old is old, OLD is old\n");
+
+    let output = run_ruplacer(tmp_dir.path(), &["-i", "old", "new", "--go"]);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "new is new, new is new\n"
+    );
+}
+
+#[test]
+fn test_smart_case() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "Old old\n");
+
+    // No upper case character: ignore case
+    let output = run_ruplacer(tmp_dir.path(), &["-S", "old", "new", "--go"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new new\n");
+
+    // An upper case character: match case
+    fs::write(&path, "Old old\n").unwrap();
+    let output = run_ruplacer(tmp_dir.path(), &["-S", "Old", "new", "--go"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new old\n");
+
+    // Escape sequences such as \S are not upper case characters
+    fs::write(&path, "Old old\n").unwrap();
+    let output = run_ruplacer(tmp_dir.path(), &["-S", r"o\S", "X", "--go"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Xd Xd\n");
+}
+
+#[test]
+fn test_fixed_strings() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "(a|o) tata\n");
+
+    let output = run_ruplacer(tmp_dir.path(), &["-F", "(a|o)", "u", "--go"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "u tata\n");
+}
+
+#[test]
+fn test_ignore_case_conflicts_with_subvert() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "foo_bar\n");
+
+    let output = run_ruplacer(
+        tmp_dir.path(),
+        &["--subvert", "-i", "foo_bar", "spam", "--go"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo_bar\n");
+}
+
+#[test]
+fn test_search_positional_arguments() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    setup_file(&tmp_dir, "old is nice\n");
+    fs::create_dir(tmp_dir.path().join("bar")).unwrap();
+    fs::write(tmp_dir.path().join("bar/baz.txt"), "old again\n").unwrap();
+
+    // With --search, every positional argument is a source path
+    let output = run_ruplacer(tmp_dir.path(), &["--search", "old", "bar", "foo.txt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("baz.txt:1 old again"));
+    assert!(stdout.contains("foo.txt:1 old is nice"));
+
+    // Otherwise, the second positional argument is always the replacement
+    let output = run_ruplacer(tmp_dir.path(), &["old", "bar", "foo.txt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("foo.txt:1 + bar is nice"));
+    assert!(!stdout.contains("baz.txt"));
+}
+
+#[test]
+fn test_configuration_precedence() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let config_home = tmp_dir.path().join("config");
+    fs::create_dir_all(config_home.join("ruplacer")).unwrap();
+    let user_config = config_home.join("ruplacer/config.toml");
+    let project = tmp_dir.path().join("project");
+    fs::create_dir(&project).unwrap();
+    let project_config = project.join(".ruplacer.toml");
+    fs::write(project.join(".hidden.txt"), "Old old\n").unwrap();
+
+    let search = |args: &[&str]| {
+        let mut all_args = vec!["--search", "old"];
+        all_args.extend(args);
+        let output = run_ruplacer_with_config(&project, &config_home, &all_args);
+        assert!(output.status.code() == Some(0) || output.status.code() == Some(2));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // The user configuration is used when there is no project configuration
+    fs::write(&user_config, "hidden = true\n").unwrap();
+    assert!(search(&[]).contains(".hidden.txt"));
+
+    // The project configuration takes precedence over the user configuration
+    fs::write(&project_config, "hidden = false\n").unwrap();
+    assert!(!search(&[]).contains(".hidden.txt"));
+
+    // The command line takes precedence over both
+    assert!(search(&["--hidden"]).contains(".hidden.txt"));
+    fs::write(&project_config, "hidden = true\n").unwrap();
+    assert!(search(&[]).contains(".hidden.txt"));
+    assert!(!search(&["--no-hidden"]).contains(".hidden.txt"));
+    assert!(search(&["--no-hidden", "--hidden"]).contains(".hidden.txt"));
+
+    // --smart-case takes precedence over ignore-case from the configuration
+    fs::write(&project_config, "hidden = true\nignore-case = true\n").unwrap();
+    assert!(search(&[]).contains("Old old"));
+    let output = run_ruplacer_with_config(&project, &config_home, &["-S", "Old", "New"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+ New old"));
+    let output =
+        run_ruplacer_with_config(&project, &config_home, &["--no-ignore-case", "old", "new"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+ Old new"));
+
+    // --no-config ignores both configuration files
+    let output = run_ruplacer(&project, &["--search", "old"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains(".hidden.txt"));
+}
+
+#[test]
+fn test_filter_listed_files() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    // Note: the project itself is in a hidden directory
+    let project = tmp_dir.path().join(".projects/foo");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::create_dir_all(project.join("other")).unwrap();
+    let files = [
+        "src/main.txt",
+        "src/other.txt",
+        "src/.hidden.txt",
+        "src/large.txt",
+        "other/main.txt",
+    ];
+    for file in &files {
+        fs::write(project.join(file), "old\n").unwrap();
+    }
+    fs::write(project.join("src/large.txt"), "old\n".repeat(100)).unwrap();
+    let absolute = |file: &str| project.join(file).to_string_lossy().into_owned();
+    let list = [
+        absolute("src/main.txt"),
+        "./src/other.txt".to_string(),
+        absolute("src/.hidden.txt"),
+        absolute("src/large.txt"),
+        absolute("other/main.txt"),
+    ];
+    fs::write(project.join("list.txt"), list.join("\n")).unwrap();
+
+    let output = run_ruplacer(
+        &project,
+        &[
+            "old",
+            "new",
+            "--files-from",
+            "list.txt",
+            "--filter-listed-files",
+            "--exclude",
+            "other/**",
+            "--max-filesize",
+            "100",
+            "--go",
+        ],
+    );
+    assert!(output.status.success());
+    let read = |file: &str| fs::read_to_string(project.join(file)).unwrap();
+    assert_eq!(read("src/main.txt"), "new\n");
+    assert_eq!(read("src/other.txt"), "new\n");
+    assert_eq!(read("src/.hidden.txt"), "old\n");
+    assert!(read("src/large.txt").starts_with("old\n"));
+    assert_eq!(read("other/main.txt"), "old\n");
+}
+
+#[test]
+fn test_print_errors_and_warnings() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "old\n");
+    fs::write(tmp_dir.path().join("list.txt"), "foo.txt\nmissing.txt\n").unwrap();
+
+    let output = run_ruplacer(
+        tmp_dir.path(),
+        &["old", "new", "--files-from", "list.txt", "--go"],
+    );
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Error: Could not read missing.txt: "));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+
+    // Symbolic links pointing to a parent directory are reported as warnings
+    #[cfg(unix)]
+    {
+        fs::create_dir(tmp_dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink("..", tmp_dir.path().join("sub/loop")).unwrap();
+        let output = run_ruplacer(tmp_dir.path(), &["new", "old", "--follow", "--go"]);
+        assert_eq!(output.status.code(), Some(0));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Warning: Could not read directory entry: "));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+    }
+}
+
+fn setup_output_files(tmp_dir: &TempDir) {
+    fs::write(tmp_dir.path().join("foo.txt"), "old old\n").unwrap();
+    fs::write(tmp_dir.path().join("bar.txt"), "old\n").unwrap();
+    fs::write(tmp_dir.path().join("baz.txt"), "nothing\n").unwrap();
+}
+
+#[test]
+fn test_files_only() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    setup_output_files(&tmp_dir);
+
+    // Note: list the files, so that the paths do not depend on the platform
+    let output = run_ruplacer(
+        tmp_dir.path(),
+        &[
+            "old",
+            "new",
+            "foo.txt",
+            "bar.txt",
+            "baz.txt",
+            "--files-only",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "foo.txt:2\n\
+         bar.txt:1\n\
+         Would perform 3 replacements on 2 matching files\n\
+         Re-run ruplacer with --go to write these changes to the filesystem\n"
+    );
+}
+
+#[test]
+fn test_stats_only() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    setup_output_files(&tmp_dir);
+
+    let output = run_ruplacer(tmp_dir.path(), &["old", "new", "--stats-only"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Would perform 3 replacements on 2 matching files\n\
+         Re-run ruplacer with --go to write these changes to the filesystem\n"
+    );
+}
+
+#[test]
+fn test_quiet() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    setup_output_files(&tmp_dir);
+
+    let output = run_ruplacer(tmp_dir.path(), &["old", "new", "--quiet"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_ruplacer(tmp_dir.path(), &["nope", "new", "--quiet"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = run_ruplacer(tmp_dir.path(), &["old", "new", "--quiet", "--go"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(tmp_dir.path().join("foo.txt")).unwrap(),
+        "new new\n"
+    );
+}
+
+#[test]
+fn test_stdin_exit_status() {
+    let output = run_ruplacer_on_stdin(&["old", "new"], "old is old\nother\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "new is new\nother\n"
+    );
+
+    let output = run_ruplacer_on_stdin(&["nope", "new"], "old is old\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "old is old\n");
+
+    let output = run_ruplacer_on_stdin(&["nope", "new", "--allow-no-match"], "old is old\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_invalid_value() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    setup_output_files(&tmp_dir);
+
+    let output = run_ruplacer(tmp_dir.path(), &["old", "new", "--scope", "nope"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'nope' is not a valid choice"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_dirty_files_hints() {
+    let tmp_dir = TempDir::new("test-ruplacer").unwrap();
+    let path = setup_file(&tmp_dir, "old\n");
+
+    let output = run_ruplacer_checking_git(tmp_dir.path(), &["old", "new", "--go"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("in a git repository, or use --allow-dirty"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("not a git repository"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+
+    let status = Command::new("git")
+        .current_dir(tmp_dir.path())
+        .args(["init", "-q"])
+        .status()
+        .expect("could not run git");
+    assert!(status.success());
+
+    let output = run_ruplacer_checking_git(tmp_dir.path(), &["old", "new", "--go"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use --allow-dirty"), "{}", stderr);
+    assert!(!stderr.contains("Settings::allow_dirty"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+}
+
+#[test]
+fn test_search_on_stdin() {
+    let input = "old is old\nother\nold\n";
+    let output = run_ruplacer_on_stdin(&["--search", "old", "--count"], input);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+    let output = run_ruplacer_on_stdin(&["--search", "old", "--files-with-matches"], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = run_ruplacer_on_stdin(&["--search", "nope"], input);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("nothing found\n"), "{}", stderr);
+}
+
+#[test]
+fn test_output_modes_on_stdin() {
+    let output = run_ruplacer_on_stdin(&["old", "new", "--quiet"], "old\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = run_ruplacer_on_stdin(&["nope", "new", "--quiet"], "old\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    for option in ["--files-only", "--stats-only", "--stats-breakdown"] {
+        let output = run_ruplacer_on_stdin(&["old", "new", option], "old\n");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("cannot be used when reading from stdin"),
+            "{}",
+            stderr
+        );
+    }
+}
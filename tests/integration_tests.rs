@@ -3,17 +3,18 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use anyhow::Result;
+use regex::Regex;
 use tempdir::TempDir;
 
 use ruplacer::Query;
-use ruplacer::Settings;
-use ruplacer::{DirectoryPatcher, Stats};
+use ruplacer::{DirectoryPatcher, Error, Outcome, Stats};
+use ruplacer::{GitSelection, OutputFormat, Scope, Settings, SettingsBuilder};
 
 fn setup_test(tmp_dir: &TempDir) -> PathBuf {
     let tmp_path = tmp_dir.path();
     #[cfg(not(target_os = "windows"))]
     let status = Command::new("cp")
-        .args(&["-R", "tests/data", &tmp_path.to_string_lossy()])
+        .args(["-R", "tests/data", &tmp_path.to_string_lossy()])
         .status()
         .expect("Failed to execute process");
     #[cfg(target_os = "windows")]
@@ -32,20 +33,26 @@ fn setup_test(tmp_dir: &TempDir) -> PathBuf {
 
 fn assert_replaced(path: &Path) {
     let contents =
-        fs::read_to_string(&path).unwrap_or_else(|_| panic!("Could not read from {:?}", path));
+        fs::read_to_string(path).unwrap_or_else(|_| panic!("Could not read from {:?}", path));
     assert!(contents.contains("new"));
     assert!(!contents.contains("old"));
 }
 
 fn assert_not_replaced(path: &Path) {
     let contents =
-        fs::read_to_string(&path).unwrap_or_else(|_| panic!("Could not read from {:?}", path));
+        fs::read_to_string(path).unwrap_or_else(|_| panic!("Could not read from {:?}", path));
     assert!(!contents.contains("new"));
     assert!(contents.contains("old"));
 }
 
+// Note: the test data is copied outside of any git repository, so
+// files can only be written with `allow_dirty`
+fn test_settings() -> SettingsBuilder {
+    Settings::builder().allow_dirty(true)
+}
+
 fn run_ruplacer(data_path: &Path, settings: Settings) -> Result<Stats> {
-    let mut directory_patcher = DirectoryPatcher::new(&data_path, &settings);
+    let mut directory_patcher = DirectoryPatcher::new(data_path, &settings);
     directory_patcher.run(&Query::substring("old", "new"))?;
     Ok(directory_patcher.stats())
 }
@@ -55,7 +62,7 @@ fn test_replace_old_by_new() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings::default();
+    let settings = test_settings().build();
     run_ruplacer(&data_path, settings).unwrap();
     let top_txt_path = data_path.join("top.txt");
     assert_replaced(&top_txt_path);
@@ -70,10 +77,23 @@ fn test_stats() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings::default();
+    let settings = test_settings().build();
     let stats = run_ruplacer(&data_path, settings).unwrap();
     assert!(stats.matching_files() > 1);
     assert!(stats.total_replacements() > 1);
+
+    let top = stats
+        .files()
+        .iter()
+        .find(|f| f.path() == data_path.join("top.txt"))
+        .unwrap();
+    assert_eq!(top.replacements(), 1);
+    assert_eq!(top.bytes_before(), top.bytes_after());
+    let txt_stats = stats
+        .file_types()
+        .into_iter()
+        .find(|t| t.file_type() == "txt");
+    assert_eq!(txt_stats.unwrap().files(), 2);
 }
 
 #[test]
@@ -81,10 +101,7 @@ fn test_dry_run() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings {
-        dry_run: true,
-        ..Default::default()
-    };
+    let settings = test_settings().dry_run(true).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     let top_txt_path = data_path.join("top.txt");
@@ -96,7 +113,7 @@ fn test_skip_hidden_and_ignored_by_default() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings::default();
+    let settings = test_settings().build();
     run_ruplacer(&data_path, settings).unwrap();
 
     let hidden_path = data_path.join(".hidden.txt");
@@ -111,10 +128,7 @@ fn test_can_replace_hidden_files() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings {
-        hidden: true,
-        ..Default::default()
-    };
+    let settings = test_settings().hidden(true).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     let hidden_path = data_path.join(".hidden.txt");
@@ -126,10 +140,7 @@ fn test_can_replace_ignored_files() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings {
-        ignored: true,
-        ..Default::default()
-    };
+    let settings = test_settings().ignored(true).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     let ignored_path = data_path.join("ignore.txt");
@@ -143,7 +154,7 @@ fn test_skip_non_utf8_files() {
     let bin_path = data_path.join("foo.latin1");
     fs::write(bin_path, b"caf\xef\n").unwrap();
 
-    let settings = Settings::default();
+    let settings = test_settings().build();
     run_ruplacer(&data_path, settings).unwrap();
 }
 
@@ -159,10 +170,7 @@ fn test_select_file_types() {
     let data_path = setup_test(&tmp_dir);
     add_python_file(&data_path);
 
-    let settings = Settings {
-        selected_file_types: vec!["py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types(["py"]).build();
     let stats = run_ruplacer(&data_path, settings).unwrap();
 
     assert_eq!(stats.matching_files(), 1);
@@ -174,10 +182,7 @@ fn test_select_file_types_by_glob_pattern_1() {
     let data_path = setup_test(&tmp_dir);
     add_python_file(&data_path);
 
-    let settings = Settings {
-        selected_file_types: vec!["*.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types(["*.py"]).build();
     let stats = run_ruplacer(&data_path, settings).unwrap();
 
     assert_eq!(stats.matching_files(), 1);
@@ -189,10 +194,7 @@ fn test_select_file_types_by_glob_pattern_2() {
     let data_path = setup_test(&tmp_dir);
     add_python_file(&data_path);
 
-    let settings = Settings {
-        selected_file_types: vec!["f*.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types(["f*.py"]).build();
     let stats = run_ruplacer(&data_path, settings).unwrap();
 
     assert_eq!(stats.matching_files(), 1);
@@ -203,12 +205,23 @@ fn test_select_file_types_by_incorrect_glob_pattern() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
 
-    let settings = Settings {
-        selected_file_types: vec!["[*.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types(["[*.py"]).build();
     let err = run_ruplacer(&data_path, settings).unwrap_err();
-    assert!(err.to_string().contains("error parsing glob"));
+    assert!(format!("{:#}", err).contains("error parsing glob"));
+}
+
+#[test]
+fn test_invalid_glob_error() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().include(["a_dir/{**"]).build();
+    let mut directory_patcher = DirectoryPatcher::new(&data_path, &settings);
+    let result = directory_patcher.run(&Query::substring("old", "new"));
+    let err = result.unwrap_err();
+    assert!(matches!(err, Error::InvalidGlob(_)));
+    let source = std::error::Error::source(&err).expect("missing source");
+    assert!(source.to_string().contains("error parsing glob"));
 }
 
 #[test]
@@ -216,10 +229,7 @@ fn test_ignore_file_types() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
     let py_path = add_python_file(&data_path);
-    let settings = Settings {
-        ignored_file_types: vec!["py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types_not(["py"]).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     assert_not_replaced(&py_path);
@@ -230,10 +240,7 @@ fn test_ignore_file_types_by_glob_pattern_1() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
     let py_path = add_python_file(&data_path);
-    let settings = Settings {
-        ignored_file_types: vec!["*.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types_not(["*.py"]).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     assert_not_replaced(&py_path);
@@ -244,10 +251,7 @@ fn test_ignore_file_types_by_glob_pattern_2() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
     let py_path = add_python_file(&data_path);
-    let settings = Settings {
-        ignored_file_types: vec!["f*.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types_not(["f*.py"]).build();
     run_ruplacer(&data_path, settings).unwrap();
 
     assert_not_replaced(&py_path);
@@ -257,10 +261,464 @@ fn test_ignore_file_types_by_glob_pattern_2() {
 fn test_ignore_file_types_by_incorrect_glob_pattern() {
     let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
     let data_path = setup_test(&tmp_dir);
-    let settings = Settings {
-        ignored_file_types: vec!["[.py".to_string()],
-        ..Default::default()
-    };
+    let settings = test_settings().types_not(["[.py"]).build();
+    let err = run_ruplacer(&data_path, settings).unwrap_err();
+    assert!(format!("{:#}", err).contains("unrecognized file type"));
+}
+
+#[test]
+fn test_search_only() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings()
+        .search_only(true)
+        .output_format(OutputFormat::Count)
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    assert!(stats.total_replacements() > 1);
+    let top_txt_path = data_path.join("top.txt");
+    assert_not_replaced(&top_txt_path);
+}
+
+#[test]
+fn test_if_contains() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings()
+        .if_contains(Regex::new("Top").unwrap())
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    assert_eq!(stats.matching_files(), 1);
+    assert_eq!(stats.skipped_files(), 2);
+    assert_replaced(&data_path.join("top.txt"));
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[test]
+fn test_unless_contains() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings()
+        .unless_contains(Regex::new("Top").unwrap())
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    assert_eq!(stats.skipped_files(), 1);
+    assert_not_replaced(&data_path.join("top.txt"));
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[test]
+fn test_restrict_scope() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    let rs_path = data_path.join("foo.rs");
+    fs::write(&rs_path, "// old\nlet old = \"old\"; /* old */\n").unwrap();
+
+    let settings = test_settings().scope(Scope::Code).build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    let actual = fs::read_to_string(&rs_path).unwrap();
+    assert_eq!(actual, "// old\nlet new = \"old\"; /* old */\n");
+    assert_eq!(stats.total_replacements(), 1);
+    // top.txt and friends are not source files, so they are skipped
+    assert_not_replaced(&data_path.join("top.txt"));
+}
+
+#[cfg(feature = "tree-sitter")]
+#[test]
+fn test_only_patch_identifiers() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    let py_path = data_path.join("foo.py");
+    fs::write(&py_path, "old_value = old('old') # old\nold.x = 42\n").unwrap();
+
+    let settings = test_settings().scope(Scope::Identifiers).build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    let actual = fs::read_to_string(&py_path).unwrap();
+    assert_eq!(actual, "old_value = new('old') # old\nnew.x = 42\n");
+    assert_eq!(stats.total_replacements(), 2);
+}
+
+#[cfg(feature = "tree-sitter")]
+#[test]
+fn test_only_patch_identifiers_in_typescript() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    let ts_path = data_path.join("foo.ts");
+    fs::write(&ts_path, "const y = <Foo>old;\nconst z = old + 1;\n").unwrap();
+    let tsx_path = data_path.join("foo.tsx");
+    fs::write(&tsx_path, "const y = <Foo>old;\nconst z = old + 1;\n").unwrap();
+
+    let settings = test_settings().scope(Scope::Identifiers).build();
+    let mut directory_patcher = DirectoryPatcher::new(&data_path, &settings);
+    let outcome = directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+
+    let actual = fs::read_to_string(&ts_path).unwrap();
+    assert_eq!(actual, "const y = <Foo>new;\nconst z = new + 1;\n");
+    // The type assertion is an unclosed JSX element in a .tsx file
+    assert_eq!(outcome, Outcome::PartialErrors);
+    let stats = directory_patcher.stats();
+    assert!(matches!(
+        stats.errors(),
+        [Error::Parse { path, .. }] if path.ends_with("foo.tsx")
+    ));
+    let actual = fs::read_to_string(&tsx_path).unwrap();
+    assert_eq!(actual, "const y = <Foo>old;\nconst z = old + 1;\n");
+}
+
+#[cfg(not(feature = "tree-sitter"))]
+#[test]
+fn test_only_patch_identifiers_requires_tree_sitter() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().scope(Scope::Identifiers).build();
     let err = run_ruplacer(&data_path, settings).unwrap_err();
-    assert!(err.to_string().contains("unrecognized file type"));
+    assert!(err.to_string().contains("tree-sitter"));
+}
+
+#[test]
+fn test_honor_ruplacerignore() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    fs::write(data_path.join(".ruplacerignore"), "top.txt\n").unwrap();
+
+    let settings = test_settings().build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    assert_not_replaced(&data_path.join("top.txt"));
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[test]
+fn test_can_disable_ruplacerignore() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    fs::write(data_path.join(".ruplacerignore"), "top.txt\n").unwrap();
+
+    let settings = test_settings().no_ruplacerignore(true).build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    assert_replaced(&data_path.join("top.txt"));
+}
+
+#[test]
+fn test_include_globs() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().include(["a_dir/**/*.txt"]).build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+
+    assert_eq!(stats.matching_files(), 1);
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+    assert_not_replaced(&data_path.join("top.txt"));
+}
+
+#[test]
+fn test_exclude_globs() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().exclude(["a_dir/**"]).build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+    assert_replaced(&data_path.join("top.txt"));
+}
+
+#[test]
+fn test_several_paths() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    // Note: a_dir/sub/foo.txt is given twice, but must only be patched once
+    let paths = vec![
+        data_path.join("a_dir"),
+        data_path.join("a_dir/sub/foo.txt"),
+        data_path.join("top.txt"),
+    ];
+    let settings = test_settings().build();
+    let mut directory_patcher = DirectoryPatcher::with_paths(&paths, &settings);
+    directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+    let stats = directory_patcher.stats();
+
+    assert_eq!(stats.matching_files(), 2);
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+    assert_replaced(&data_path.join("top.txt"));
+    assert_not_replaced(&data_path.join("no-newline"));
+}
+
+#[test]
+fn test_listed_files() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    // Note: listed files bypass the walker, so ignore rules do not apply
+    let files = vec![data_path.join("top.txt"), data_path.join("ignore.txt")];
+    let settings = test_settings().build();
+    let mut directory_patcher = DirectoryPatcher::with_files(&files, &settings);
+    directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+
+    assert_replaced(&data_path.join("top.txt"));
+    assert_replaced(&data_path.join("ignore.txt"));
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[test]
+fn test_filter_listed_files() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let files = vec![data_path.join("top.txt"), data_path.join("no-newline")];
+    let settings = test_settings()
+        .filter_listed_files(true)
+        .types(["*.txt"])
+        .build();
+    let mut directory_patcher = DirectoryPatcher::with_files(&files, &settings);
+    directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+
+    assert_replaced(&data_path.join("top.txt"));
+    assert_not_replaced(&data_path.join("no-newline"));
+}
+
+fn run_git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=ruplacer",
+            "-c",
+            "user.email=ruplacer@example.com",
+        ])
+        .args(args)
+        .status()
+        .expect("Failed to execute git");
+    assert!(status.success());
+}
+
+#[test]
+fn test_git_selection() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    run_git(&data_path, &["init", "-q"]);
+    run_git(&data_path, &["add", "top.txt", "a_dir"]);
+    run_git(&data_path, &["commit", "-q", "-m", "initial commit"]);
+    fs::write(data_path.join("top.txt"), "old is new\n").unwrap();
+
+    let settings = Settings::builder()
+        .dry_run(true)
+        .git_selection(GitSelection::Tracked)
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+    assert_eq!(stats.matching_files(), 2);
+
+    let settings = Settings::builder()
+        .dry_run(true)
+        .git_selection(GitSelection::Staged)
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+    assert_eq!(stats.matching_files(), 0);
+
+    let settings = Settings::builder()
+        .git_selection(GitSelection::ChangedSince("HEAD".to_string()))
+        .allow_dirty(true)
+        .build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+    assert_eq!(stats.matching_files(), 1);
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+    assert_not_replaced(&data_path.join("no-newline"));
+}
+
+#[test]
+fn test_refuse_to_patch_dirty_files() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    run_git(&data_path, &["init", "-q"]);
+    run_git(&data_path, &["add", "a_dir"]);
+    run_git(&data_path, &["commit", "-q", "-m", "initial commit"]);
+
+    // top.txt is not tracked
+    let err = run_ruplacer(&data_path.join("top.txt"), Settings::default()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::DirtyFiles { paths }) if paths.len() == 1 && paths[0].ends_with("top.txt")
+    ));
+    assert_not_replaced(&data_path.join("top.txt"));
+
+    run_ruplacer(&data_path.join("a_dir"), Settings::default()).unwrap();
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+
+    let settings = Settings::builder().allow_dirty(true).build();
+    run_ruplacer(&data_path.join("top.txt"), settings).unwrap();
+    assert_replaced(&data_path.join("top.txt"));
+}
+
+#[test]
+fn test_refuse_to_patch_outside_git_repositories() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let err = run_ruplacer(&data_path, Settings::default()).unwrap_err();
+    match err.downcast_ref::<Error>() {
+        Some(Error::UncheckedFiles { source }) => assert!(matches!(**source, Error::Git { .. })),
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert_not_replaced(&data_path.join("top.txt"));
+
+    let settings = Settings::builder().dry_run(true).build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    run_ruplacer(&data_path, test_settings().build()).unwrap();
+    assert_replaced(&data_path.join("top.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_keep_writing_after_a_write_error() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    let read_only_path = data_path.join("top.txt");
+    let mut permissions = fs::metadata(&read_only_path).unwrap().permissions();
+    permissions.set_mode(0o444);
+    fs::set_permissions(&read_only_path, permissions).unwrap();
+    // Note: root can write to read-only files
+    if fs::OpenOptions::new()
+        .write(true)
+        .open(&read_only_path)
+        .is_ok()
+    {
+        return;
+    }
+
+    let settings = test_settings().build();
+    let mut directory_patcher = DirectoryPatcher::new(&data_path, &settings);
+    let outcome = directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+    assert_eq!(outcome, Outcome::PartialErrors);
+    assert_replaced(&data_path.join("a_dir/sub/foo.txt"));
+    assert_not_replaced(&read_only_path);
+    let stats = directory_patcher.stats();
+    assert!(matches!(
+        stats.errors(),
+        [Error::Write { path, .. }] if path.ends_with("top.txt")
+    ));
+}
+
+#[test]
+fn test_refuse_to_patch_anything_when_a_file_is_dirty() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    run_git(&data_path, &["init", "-q"]);
+    run_git(&data_path, &["add", "a_dir"]);
+    run_git(&data_path, &["commit", "-q", "-m", "initial commit"]);
+
+    // The clean file is visited first, and must not be written either
+    let clean_path = data_path.join("a_dir/sub/foo.txt");
+    let dirty_path = data_path.join("top.txt");
+    let paths = [clean_path.clone(), dirty_path.clone()];
+    let settings = Settings::default();
+    let mut directory_patcher = DirectoryPatcher::with_paths(&paths, &settings);
+    let err = directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap_err();
+    match err {
+        Error::DirtyFiles { paths } => assert_eq!(paths, std::slice::from_ref(&dirty_path)),
+        other => panic!("unexpected error: {}", other),
+    }
+    assert_not_replaced(&clean_path);
+    assert_not_replaced(&dirty_path);
+}
+
+#[test]
+fn test_max_depth() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().max_depth(1).build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    assert_replaced(&data_path.join("top.txt"));
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[test]
+fn test_max_filesize() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    // top.txt is 56 bytes long, a_dir/sub/foo.txt is 91 bytes long
+    let settings = test_settings().max_filesize(60).build();
+    run_ruplacer(&data_path, settings).unwrap();
+
+    assert_replaced(&data_path.join("top.txt"));
+    assert_not_replaced(&data_path.join("a_dir/sub/foo.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_links() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+    let vendor_path = tmp_dir.path().join("vendor");
+    fs::create_dir(&vendor_path).unwrap();
+    fs::write(vendor_path.join("lib.txt"), "old\n").unwrap();
+    std::os::unix::fs::symlink(&vendor_path, data_path.join("vendor")).unwrap();
+    // Make sure loops do not abort the run
+    std::os::unix::fs::symlink(&data_path, data_path.join("a_dir/loop")).unwrap();
+
+    let settings = test_settings().dry_run(true).build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+    assert_eq!(stats.matching_files(), 3);
+
+    let settings = test_settings().follow_links(true).build();
+    let stats = run_ruplacer(&data_path, settings).unwrap();
+    assert_eq!(stats.matching_files(), 4);
+    assert_replaced(&vendor_path.join("lib.txt"));
+}
+
+#[test]
+fn test_outcome() {
+    let tmp_dir = TempDir::new("test-ruplacer").expect("failed to create temp dir");
+    let data_path = setup_test(&tmp_dir);
+
+    let settings = test_settings().build();
+    let mut directory_patcher = DirectoryPatcher::new(&data_path, &settings);
+    let outcome = directory_patcher
+        .run(&Query::substring("nope", "new"))
+        .unwrap();
+    assert_eq!(outcome, Outcome::NothingFound);
+
+    // Files that cannot be read are skipped, but do not abort the run
+    let files = vec![data_path.join("missing.txt"), data_path.join("top.txt")];
+    let mut directory_patcher = DirectoryPatcher::with_files(&files, &settings);
+    let outcome = directory_patcher
+        .run(&Query::substring("old", "new"))
+        .unwrap();
+    assert_eq!(outcome, Outcome::PartialErrors);
+    let stats = directory_patcher.stats();
+    assert_eq!(stats.errors().len(), 1);
+    assert!(matches!(
+        &stats.errors()[0],
+        Error::Read { path, .. } if path.ends_with("missing.txt")
+    ));
+    assert_replaced(&data_path.join("top.txt"));
 }